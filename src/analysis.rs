@@ -1,118 +1,972 @@
+use std::{thread::{self, spawn, JoinHandle}, sync::{atomic::{AtomicBool, AtomicU64, Ordering::Relaxed}, mpsc::{self, RecvTimeoutError, TryRecvError}}, time};
+use crate::{ScreenManager, board::{Board, Move, Player}, eval::Evaluator, mcts::{self, Playout, Tree}, solver::{Solution, Solver}, transposition::{Bound, Entry, TranspositionTable}, weights::MAX_WIN};
 
-use std::{thread::{spawn, JoinHandle}, sync::mpsc::{self, TryRecvError}, collections::{HashMap, VecDeque}, time};
-use crate::{ScreenManager, board::Board};
+const INFINITY: i32 = MAX_WIN + 1;
 
-pub fn spawn_analysis_thread(screen: ScreenManager, 
+// Heuristic search stops here and hands off to the solver. Also as deep as an engine can
+// be asked to search.
+pub const HEURISTIC_DEPTH: i32 = 10;
+
+// Playouts between checking in, for Monte Carlo, and how many before it stops. By then the
+// numbers hardly move.
+const MCTS_BATCH: u32 = 256;
+const MCTS_PLAYOUTS: u64 = 20_000_000;
+
+// The table depth limited moves get, see Link::answer. Those searches are small.
+const DEPTH_TABLE_MEGABYTES: usize = 16;
+
+// How the analysis goes about a position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Minimax,  // Heuristic search, then the solver
+    Mcts (Playout),
+}
+
+// What the analysis makes of one of the root's moves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveScore {
+    Heuristic (i32),  // For the player to move, from the search
+    Solved (Solution),  // Of the position after it, plies counted from before the move
+}
+
+impl MoveScore {
+    // For sorting, higher is better for the player to move. Quick wins beat slow ones beat
+    // any search score, and a draw counts as an even score.
+    fn rank(&self, player: Player) -> (i32, i32) {
+        match *self {
+            MoveScore::Heuristic(score) => (1, score),
+            MoveScore::Solved(Solution { winner: None, .. }) => (1, 0),
+            MoveScore::Solved(Solution { winner: Some(winner), plies, .. }) if winner == player => (2, -plies),
+            MoveScore::Solved(Solution { plies, .. }) => (0, plies),
+        }
+    }
+}
+
+// A move from the root and how play is expected to go on from there.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub moves: Vec<Move>,
+    pub score: MoveScore,
+}
+
+// Fixed for as long as the analysis thread runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub hash_megabytes: usize,
+    pub threads: usize,  // For the heuristic search. The solver and Monte Carlo use one
+    pub lines: usize,  // How many of the best lines to show
+}
+
+// A position for the analysis, and how to go about it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Root {
+    board: Board,
+    evaluator: &'static dyn Evaluator,
+    method: Method,
+    ponder: bool,  // Really the position after the reply expected to `board`
+}
+
+pub enum Request {
+    NewRoot (Root),
+    BestMove (Limits, mpsc::Sender<Option<Move>>),
+}
+
+// When to stop thinking and answer a BestMove. Whichever comes first.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub time: time::Duration,
+    pub depth: Option<i32>,  // Heuristic search depth
+}
+
+struct Waiting {
+    deadline: time::Instant,
+    depth: Option<i32>,
+    reply: mpsc::Sender<Option<Move>>,
+}
+
+// Handle to the analysis thread. Cheap to clone, the thread goes away once every handle has.
+#[derive(Clone)]
+pub struct Analysis {
+    sender: mpsc::Sender<Request>,
+}
+
+impl Analysis {
+    pub fn new(screen: ScreenManager, root_board: Board, evaluator: &'static dyn Evaluator, method: Method, config: Config) -> Analysis {
+        let (sender, receiver) = mpsc::channel();
+        let _thread = spawn_analysis_thread(screen, root_board, evaluator, method, receiver, config);
+
+        Analysis { sender }
+    }
+
+    // The evaluator and method are whatever the engine about to ask for a move goes by.
+    pub fn set_root(&self, board: Board, evaluator: &'static dyn Evaluator, method: Method) {
+        self.sender.send(Request::NewRoot(Root { board, evaluator, method, ponder: false })).expect("sends");
+    }
+
+    // Pondering. While the other side thinks over `board`, the engine to play after them
+    // gets a head start on the position after the reply it expects. If that's what they
+    // play, set_root finds the work already going. Without a guess it looks at `board`.
+    pub fn ponder(&self, board: Board, evaluator: &'static dyn Evaluator, method: Method) {
+        self.sender.send(Request::NewRoot(Root { board, evaluator, method, ponder: true })).expect("sends");
+    }
+
+    // Blocks until the limits are hit (or the position gets solved). Uses the current
+    // root, so set that first. None if the game is over there.
+    pub fn best_move(&self, limits: Limits) -> Option<Move> {
+        let (sender, receiver) = mpsc::channel();
+        self.sender.send(Request::BestMove(limits, sender)).expect("sends");
+        receiver.recv().expect("analysis answers")
+    }
+}
+
+fn spawn_analysis_thread(screen: ScreenManager,
         mut root_board: Board,
-        receiver: mpsc::Receiver<Board>) -> JoinHandle<()> {
+        mut evaluator: &'static dyn Evaluator,
+        mut method: Method,
+        receiver: mpsc::Receiver<Request>,
+        config: Config) -> JoinHandle<()> {
 
-    let mut last_update = time::Instant::now();    
-    
-    return spawn(move || {
-        let mut evaluated_boards: HashMap<Board, i32> = HashMap::new(); // Maps to value
-        let mut boundary: VecDeque<Board> = VecDeque::new();  // Boards to analyze soon. BFS style queue.
+    spawn(move || {
+        let root = Root { board: root_board.clone(), evaluator, method, ponder: false };
+        let counters = Counters::default();
 
-        evaluated_boards.insert(root_board.clone(), root_board.get_score());
-        boundary.extend(root_board.next_boards());
+        // One table for everything. The solver tags its keys so the two never mix.
+        let table = TranspositionTable::with_memory(config.hash_megabytes);
+        let depth_table = TranspositionTable::with_memory(DEPTH_TABLE_MEGABYTES);
+
+        let mut link = Link {
+            receiver: &receiver,
+            screen: &screen,
+            counters: &counters,
+            depth_table: &depth_table,
+            threads: config.threads,
+            last_update: time::Instant::now(),
+            last_nodes: 0,
+            new_root: None,
+            asked: root.clone(),
+            searching: root,
+            hung_up: false,
+            best_move: None,
+            completed_depth: 0,
+            forced: false,
+            waiting: None,
+        };
+
+        // Monte Carlo's tree from the last root, kept for if the next one is somewhere in it.
+        let mut tree: Option<Tree> = None;
 
         loop {
-            if time::Instant::now() - last_update > time::Duration::from_millis(200) {
-                match receiver.try_recv() {
-                    Ok(board) => {
-                        root_board = board;
-                        
-                        send_root_info(&mut evaluated_boards, &root_board, &screen);
-
-                        boundary = VecDeque::new();
-    
-                        // if !evaluated_boards.contains_key(&root_board) {
-                        //     evaluated_boards.insert(root_board.clone(), root_board.get_score());
-                        // }
-
-                        boundary.push_back(root_board.clone());
-                    }
-                    Err(TryRecvError::Disconnected) => {
-                        panic!("They hung up!")
+            screen.update_solution(None);
+            screen.update_mcts(vec![]);
+            screen.update_move_scores(vec![]);
+            screen.update_lines(vec![]);
+            let last_tree = tree.take();
+            link.best_move = None;
+            link.completed_depth = 0;
+            link.forced = false;
+
+            if let Some(player) = root_board.next_to_move() {
+                // Checked up front, so even the shortest search can't miss a win or a block.
+                let forced = root_board.forced_moves().filter(|moves| !moves.is_empty());
+                let win = root_board.winning_moves(player).first().copied();
+
+                if let Some(win) = win {
+                    // Nothing better to find.
+                    link.best_move = Some(win);
+                    link.forced = true;
+                    send_root_info(&screen, player, evaluator.weights().win - 1, Some(win), 1);
+                    screen.update_move_scores(vec![(win, MoveScore::Solved(Solution { winner: Some(player), plies: 1, best_move: None }))]);
+                }
+                else if let Method::Mcts(playout) = method {
+                    let max_nodes = mcts::max_nodes(config.hash_megabytes);
+
+                    // Carry on from the old tree if this position is in it. Not when the moves
+                    // are forced though, the old one would look at all of them.
+                    let kept = last_tree.filter(|_| forced.is_none()).and_then(|last| last.reroot(&root_board, playout));
+                    let mut current = kept.unwrap_or_else(|| Tree::new(root_board.clone(), forced.as_deref(), playout, max_nodes, root_board.key()));
+                    screen.update_root_score(evaluator.score(&root_board));
+
+                    // Answered after the first batch, there's nothing to weigh up.
+                    link.forced = forced.as_ref().is_some_and(|moves| moves.len() == 1);
+
+                    // Whoever wants a move takes what's best so far once their time is up.
+                    let mut last_update = time::Instant::now();
+                    while !link.abandoned() && current.playouts() < MCTS_PLAYOUTS {
+                        current.run(MCTS_BATCH);
+                        counters.nodes.fetch_add(MCTS_BATCH as u64, Relaxed);
+                        link.best_move = current.best_move();
+
+                        if time::Instant::now() - last_update > time::Duration::from_millis(200) {
+                            send_tree_info(&screen, &current);
+                            last_update = time::Instant::now();
+                        }
+
+                        link.poll();
                     }
-                    Err(_) => (),
+
+                    send_tree_info(&screen, &current);
+                    screen.update_analysis_count(link.nodes());
+                    tree = Some(current);
                 }
+                else {
+                    if let Some([only]) = forced.as_deref() {
+                        // Anything else loses on the spot. The search still works out the score.
+                        link.best_move = Some(*only);
+                        link.forced = true;
+                        screen.update_recomended_move(link.best_move);
+                        link.answer(false);
+                    }
 
-                last_update = time::Instant::now();
-                screen.update_analysis_count(evaluated_boards.len() as i32);
-            }
+                    let mut move_scores = vec![];
+                    let max_depth = root_board.plies_left().map_or(HEURISTIC_DEPTH, |plies| plies.min(HEURISTIC_DEPTH));
 
-            let Some(curr_board) = boundary.pop_front()
-                else { continue };
+                    // Iterative deepening. Each finished depth is reported, then we go one deeper
+                    // until we hit the depth limit or a new root shows up.
+                    for depth in 1..=max_depth {
+                        let Some(RootResult { score, best_move: best, moves }) = parallel_search(&root_board, player, depth, &table, &mut link, evaluator, forced.as_deref())
+                            else { break };
+
+                        link.best_move = best;
+                        link.completed_depth = depth;
+                        send_root_info(&screen, player, score, best, depth);
+                        move_scores = moves.into_iter().map(|(m, score)| (m, move_score(player, score, evaluator.weights().win))).collect();
+                        screen.update_move_scores(with_mirrors(&root_board, &move_scores));
+                        screen.update_lines(best_lines(&table, &root_board, &move_scores, config.lines, depth));
+                        screen.update_analysis_count(link.nodes());
+                        link.answer(false);
+
+                        // A forced result is exact, searching deeper won't change it.
+                        if score.abs() > evaluator.weights().win - 100 {
+                            break;
+                        }
+                    }
+
+                    // Now for the exact answer. This can take a long while early in the game.
+                    if !link.abandoned() && root_board.rules().solvable() {
+                        let start_nodes = link.nodes();
+                        let mut solver = Solver::new(&table);
+
+                        let solution = solver.solve(&root_board, &mut |nodes| {
+                            counters.nodes.store(start_nodes + nodes, Relaxed);
+                            link.poll()
+                        });
 
-            if !evaluated_boards.contains_key(&curr_board) {
-                evaluated_boards.insert(curr_board.clone(), curr_board.get_score());
+                        if let Some(solution) = solution {
+                            link.best_move = solution.best_move.map(Move::Drop);
+                            screen.update_solution(Some(solution));
+                            screen.update_recomended_move(link.best_move);
+                            screen.update_analysis_count(link.nodes());
 
-                update_parents(&mut evaluated_boards, &curr_board, &root_board, &screen);
+                            // Then each move on its own, which goes quicker now the table
+                            // knows the position.
+                            for m in root_board.legal_moves() {
+                                let child = root_board.play_move(m, player, false).expect("legal");
+                                let solution = solver.solve(&child, &mut |nodes| {
+                                    counters.nodes.store(start_nodes + nodes, Relaxed);
+                                    link.poll()
+                                });
+                                let Some(solution) = solution
+                                    else { break };
+
+                                let solved = MoveScore::Solved(Solution { plies: solution.plies + 1, ..solution });
+                                match move_scores.iter_mut().find(|(scored, _)| *scored == m) {
+                                    Some(entry) => entry.1 = solved,
+                                    None => move_scores.push((m, solved)),
+                                }
+                                screen.update_move_scores(with_mirrors(&root_board, &move_scores));
+                                screen.update_lines(best_lines(&table, &root_board, &move_scores, config.lines, link.completed_depth));
+                            }
+                        }
+                    }
+                }
+            }
+            else {
+                screen.update_root_score(evaluator.score(&root_board));
+                screen.update_recomended_move(None);
+                screen.update_search_depth(0);
             }
 
+            // Nothing running until the next root.
+            screen.update_throughput(0, config.threads);
+
+            let asked = loop {
+                if link.hung_up {
+                    return;
+                }
+
+                if let Some(root) = link.new_root.take() {
+                    break root;
+                }
+
+                // Nothing left to search, so what we have is as good as it gets.
+                link.answer(true);
+
+                // Sleep until the next request.
+                match receiver.recv() {
+                    Ok(request) => link.handle(request),
+                    Err(_) => return,
+                }
+            };
 
-            /* Can this be made better with killer move optimization? */
-            if let None = curr_board.winner() {
-                boundary.extend(curr_board.next_boards());
+            // Guessed before the table gets touched, since the guess comes out of it.
+            let expected = asked.ponder.then(|| predict(&table, tree.as_ref(), &asked.board)).flatten();
+            let new_board = match (expected, asked.board.next_to_move()) {
+                (Some(m), Some(player)) => asked.board.play_move(m, player, false).expect("predicted moves are legal"),
+                _ => asked.board.clone(),
+            };
+            screen.update_pondering(expected);
+
+            link.searching = Root { board: new_board.clone(), ponder: false, ..asked.clone() };
+            link.asked = asked.clone();
+            method = asked.method;
+
+            // Scores from another evaluator would only mislead.
+            if *asked.evaluator != *evaluator {
+                table.clear();
+                evaluator = asked.evaluator;
+            }
+            else if root_board.can_reach(&new_board) {
+                // Moves got played. The table still has everything searched below them, and
+                // the rest can't come up again, so it makes room.
+                prune(&table, &new_board);
+            }
+            else {
+                // Somewhere else entirely, like after an undo. The old entries go first.
+                table.new_search();
             }
+
+            root_board = new_board;
         }
     })
 }
 
-fn send_root_info(evaluated_boards: &mut HashMap<Board, i32>, root_board: &Board, screen: &ScreenManager) {
-    screen.update_root_score(evaluated_boards[root_board]);
+// Throws out entries for positions that can't come up after `root`, returning how many.
+// Positions are stored as either themselves or their mirror, so both get a look. Keys
+// that can't be turned back into boards (big boards, the solver's) only go if they have
+// fewer pieces than the root.
+fn prune(table: &TranspositionTable, root: &Board) -> usize {
+    let mirrored = root.mirrored();
+    let pieces = root.pieces_played();
+
+    table.retain(|entry| match root.board_for_key(entry.key) {
+        Some(board) => root.can_reach(&board) || mirrored.can_reach(&board),
+        None => entry.pieces as i32 >= pieces,
+    })
+}
+
+// The reply we expect to `board`, going by the search that was just running.
+fn predict(table: &TranspositionTable, tree: Option<&Tree>, board: &Board) -> Option<Move> {
+    tree.and_then(|tree| tree.best_move_at(board))
+        .or_else(|| principal_variation(table, board.clone(), 1).first().copied())
+}
+
+fn send_tree_info(screen: &ScreenManager, tree: &Tree) {
+    screen.update_mcts(tree.stats());
+    screen.update_recomended_move(tree.best_move());
+    screen.update_search_depth(tree.depth());
+}
+
+// A search score, unless it's a forced result. Those are exact, and the ply they're counted
+// down by is how far off the end is.
+fn move_score(player: Player, score: i32, win: i32) -> MoveScore {
+    let solved = |winner, plies| MoveScore::Solved(Solution { winner: Some(winner), plies, best_move: None });
+
+    if score > win - 100 {
+        solved(player, win - score)
+    }
+    else if score < -win + 100 {
+        solved(player.opponent(), win + score)
+    }
+    else {
+        MoveScore::Heuristic(score)
+    }
+}
+
+// The best `count` moves, each followed by the line the table expects, up to `depth` long.
+fn best_lines(table: &TranspositionTable, root: &Board, scores: &[(Move, MoveScore)], count: usize, depth: i32) -> Vec<Line> {
+    let Some(player) = root.next_to_move()
+        else { return vec![] };
+
+    let mut ranked = scores.to_vec();
+    ranked.sort_by_key(|(_, score)| std::cmp::Reverse(score.rank(player)));
+
+    ranked.into_iter()
+        .take(count)
+        .map(|(m, score)| {
+            let mut moves = vec![m];
+            let board = root.play_move(m, player, false).expect("searched moves are legal");
+            moves.extend(principal_variation(table, board, depth - 1));
+            Line { moves, score }
+        })
+        .collect()
+}
+
+// Follows the table's best moves from `board`. Entries get overwritten, so the line can
+// come up short, but every move in it is legal.
+fn principal_variation(table: &TranspositionTable, mut board: Board, depth: i32) -> Vec<Move> {
+    let mut moves = vec![];
+
+    while (moves.len() as i32) < depth {
+        let Some(player) = board.next_to_move()
+            else { break };
+
+        let (key, flipped) = table_key(&board);
+        let Some(m) = table.get(key).and_then(|entry| Move::from_index(entry.best_move as i32))
+            else { break };
+
+        let m = orient(&board, m, flipped);
+        let Ok(next) = board.play_move(m, player, false)
+            else { break };
+
+        moves.push(m);
+        board = next;
+    }
 
-    let Some(player) = root_board.next_to_move()
-        else {return};
+    moves
+}
 
-    let mut next_move = -1;
-    for col in 0..7 {
-        let Ok(child) = root_board.play(col, player, false)
-            else {continue};
+// The search skips mirror image moves in a symmetric position. They score the same.
+fn with_mirrors(board: &Board, scores: &[(Move, MoveScore)]) -> Vec<(Move, MoveScore)> {
+    let mut all = scores.to_vec();
 
-        match evaluated_boards.get(&child) {
-            Some(val) if val == &evaluated_boards[root_board] => {
-                next_move = col;
-            },
-            _ => (),
+    if board.mirrored() == *board {
+        for (m, score) in scores {
+            let mirrored = m.mirrored(board.geometry());
+            if !all.iter().any(|(scored, _)| *scored == mirrored) {
+                all.push((mirrored, *score));
+            }
         }
     }
-    
+
+    all
+}
+
+// Lazy SMP. Helper threads search the same root alongside the main one, half of them a
+// ply deeper, with nothing shared but the table and the counters. What they leave in the
+// table is the point: the main search finds more cutoffs and finishes sooner. Only the
+// main search's result is used.
+fn parallel_search(root: &Board,
+        player: Player,
+        depth: i32,
+        table: &TranspositionTable,
+        link: &mut Link,
+        evaluator: &'static dyn Evaluator,
+        root_moves: Option<&[Move]>) -> Option<RootResult> {
+
+    let counters = link.counters;
+    counters.stop.store(false, Relaxed);
+
+    thread::scope(|scope| {
+        for helper in 1..link.threads {
+            scope.spawn(move || {
+                let mut search = Search { table, link: None, counters, nodes: 0, evaluator, root_moves, helper, exact: false };
+                search.search_root(root, player, depth + helper as i32 % 2);
+            });
+        }
+
+        let mut search = Search { table, link: Some(link), counters, nodes: 0, evaluator, root_moves, helper: 0, exact: false };
+        let result = search.search_root(root, player, depth);
+        counters.stop.store(true, Relaxed);
+
+        result
+    })
+}
+
+// Score is from the perspective of the player to move, the screen wants it Red positive.
+fn send_root_info(screen: &ScreenManager, player: Player, score: i32, next_move: Option<Move>, depth: i32) {
+    screen.update_root_score(match player {
+        Player::Red => score,
+        Player::Yellow => -score,
+    });
     screen.update_recomended_move(next_move);
+    screen.update_search_depth(depth);
 }
 
-fn update_parents(evaluated_boards: &mut HashMap<Board, i32>, curr_board: &Board, root_board: &Board, screen: &ScreenManager) {
-    if curr_board == root_board {
-        send_root_info(evaluated_boards, root_board, screen);
-        return;
+// Score from the point of view of `player`. Wins are pulled towards zero by the ply they
+// happen at, so faster wins (and slower losses) are preferred.
+fn evaluate(evaluator: &dyn Evaluator, board: &Board, player: Player, ply: i32) -> i32 {
+    let score = match player {
+        Player::Red => evaluator.score(board),
+        Player::Yellow => -evaluator.score(board),
+    };
+
+    let win = evaluator.weights().win;
+
+    if score >= win {
+        score - ply
     }
+    else if score <= -win {
+        score + ply
+    }
+    else {
+        score
+    }
+}
 
-    let parent_boards = curr_board.prev_boards();
+// Win scores count from the root, but the table wants them counted from the position
+// itself, since it can be reached at different plies.
+fn to_table(score: i32, ply: i32, win: i32) -> i32 {
+    if score > win - 100 {
+        score + ply
+    }
+    else if score < -win + 100 {
+        score - ply
+    }
+    else {
+        score
+    }
+}
 
-    for parent_board in parent_boards {
-        let parent_player = parent_board.next_to_move().unwrap();  // Is this repeated? Yes.
-        // But there's a chance of getting none (on win) if we do it above...
+fn from_table(score: i32, ply: i32, win: i32) -> i32 {
+    to_table(score, -ply, win)
+}
 
-        if !evaluated_boards.contains_key(&parent_board) {
-            continue;
+// A position and its mirror image share a table entry, keyed by whichever is canonical.
+// Also says whether the board is flipped relative to that, so moves can be mapped.
+fn table_key(board: &Board) -> (u64, bool) {
+    let canonical = board.canonical();
+    (canonical.key(), canonical != *board)
+}
+
+// Moves are stored as played on the canonical board. This maps them either way.
+fn orient(board: &Board, m: Move, flipped: bool) -> Move {
+    if flipped {
+        m.mirrored(board.geometry())
+    }
+    else {
+        m
+    }
+}
+
+// What goes in an entry's best_move, -1 for none.
+fn table_move(board: &Board, m: Option<Move>, flipped: bool) -> i32 {
+    m.map_or(-1, |m| orient(board, m, flipped).to_index())
+}
+
+// Shared by the search threads. Nodes get added in batches, so the count runs a little
+// behind.
+#[derive(Default)]
+struct Counters {
+    nodes: AtomicU64,
+    stop: AtomicBool,  // Tells the helpers the main search is done with this depth
+}
+
+// The analysis thread's connection to the rest of the program.
+struct Link<'a> {
+    receiver: &'a mpsc::Receiver<Request>,
+    screen: &'a ScreenManager,
+    counters: &'a Counters,
+    depth_table: &'a TranspositionTable,  // Only for depth limited moves, see answer
+    threads: usize,
+    last_update: time::Instant,
+    last_nodes: u64,  // As of last_update, for the nodes per second
+    new_root: Option<Root>,  // Set when the search was abandoned for a new position.
+    asked: Root,  // The request the current search came from
+    searching: Root,  // And what that turned out to mean
+    hung_up: bool,
+    best_move: Option<Move>,  // Best so far for the current root
+    completed_depth: i32,
+    forced: bool,  // best_move is the only sensible move, no need to wait for limits
+    waiting: Option<Waiting>,  // Someone wants a move
+}
+
+impl Link<'_> {
+    fn nodes(&self) -> u64 {
+        self.counters.nodes.load(Relaxed)
+    }
+
+    fn abandoned(&self) -> bool {
+        self.new_root.is_some() || self.hung_up
+    }
+
+    fn handle(&mut self, request: Request) {
+        match request {
+            Request::NewRoot(root) => {
+                // Already on it, most likely because the move we pondered got played. Only
+                // if nothing else is waiting though, the latest request is the one that counts.
+                if self.new_root.is_some() || (root != self.asked && root != self.searching) {
+                    self.new_root = Some(root);
+                }
+                else if root != self.asked {
+                    self.asked = root;
+                    self.screen.update_pondering(None);
+                }
+            }
+            Request::BestMove(limits, reply) => {
+                self.waiting = Some(Waiting {
+                    deadline: time::Instant::now() + limits.time,
+                    depth: limits.depth,
+                    reply,
+                });
+            }
+        }
+    }
+
+    // Hands over the best move if a limit was hit (or `now` says not to wait).
+    fn answer(&mut self, now: bool) {
+        let Some(waiting) = &self.waiting
+            else { return };
+
+        if self.new_root.is_some() {
+            return;
+        }
+
+        // A depth limit gets a search of its own, one that's exactly that deep whatever the
+        // analysis has got up to and however many threads it has. In a table of its own,
+        // emptied first, so nothing the analysis found on the other side's turn leaks in.
+        if let Some(depth) = waiting.depth {
+            let waiting = self.waiting.take().unwrap();
+            let searching = &self.searching;
+            self.depth_table.clear();
+            _ = waiting.reply.send(fixed_depth_move(&searching.board, searching.evaluator, depth, self.depth_table, Some(waiting.deadline)));
+            return;
+        }
+
+        let limit_hit = time::Instant::now() >= waiting.deadline;
+
+        if now || self.forced || (limit_hit && self.best_move.is_some()) {
+            let waiting = self.waiting.take().unwrap();
+            _ = waiting.reply.send(self.best_move);  // They may have given up, that's fine.
+        }
+    }
+
+    // Reports progress and checks for requests. True if the current search should be
+    // abandoned.
+    fn poll(&mut self) -> bool {
+        let elapsed = time::Instant::now() - self.last_update;
+        if elapsed > time::Duration::from_millis(200) {
+            let nodes = self.nodes();
+            let per_second = (nodes.saturating_sub(self.last_nodes)) as f64 / elapsed.as_secs_f64();
+
+            self.screen.update_analysis_count(nodes);
+            self.screen.update_throughput(per_second as u64, self.threads);
+            self.last_update = time::Instant::now();
+            self.last_nodes = nodes;
+        }
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(request) => self.handle(request),  // Only the latest board matters.
+                Err(TryRecvError::Disconnected) => {
+                    self.hung_up = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
         }
 
-        let siblings = parent_board.next_boards();
+        self.answer(false);
 
-        let scores = siblings.iter()
-            .map(|b| evaluated_boards.get(b))
-            .flatten()
-            .map(|i| i.clone());
+        self.abandoned()
+    }
+}
+
+// The move a search of exactly `depth` plies settles on. Wins and forced blocks first, like
+// the analysis. One thread, and only table entries exactly that deep get to cut the search
+// short, so the same position always gets the same move however much was searched before.
+// For depth limited engines, and self-play. `table` can be kept between moves, but only for
+// one evaluator. Past the deadline, the deepest move finished so far.
+pub fn fixed_depth_move(board: &Board, evaluator: &dyn Evaluator, depth: i32, table: &TranspositionTable, deadline: Option<time::Instant>) -> Option<Move> {
+    let player = board.next_to_move()?;
+
+    if let Some(&win) = board.winning_moves(player).first() {
+        return Some(win);
+    }
+
+    let forced = board.forced_moves().filter(|moves| !moves.is_empty());
+    if let Some([only]) = forced.as_deref() {
+        return Some(*only);
+    }
+
+    let counters = &Counters::default();
+    let (done, finished) = mpsc::channel::<()>();
 
-        let score = match parent_player {
-            crate::board::Player::Red => scores.max(),
-            crate::board::Player::Yellow => scores.min(),
-        }.unwrap();
+    thread::scope(|scope| {
+        if let Some(deadline) = deadline {
+            // Wakes at the deadline, or as soon as the search drops `done`.
+            scope.spawn(move || {
+                let wait = deadline.saturating_duration_since(time::Instant::now());
+                if finished.recv_timeout(wait) == Err(RecvTimeoutError::Timeout) {
+                    counters.stop.store(true, Relaxed);
+                }
+            });
+        }
 
-        if evaluated_boards[&parent_board] != score {
-            evaluated_boards.insert(parent_board.clone(), score);
+        let mut search = Search { table, link: None, counters, nodes: 0, evaluator, root_moves: forced.as_deref(), helper: 0, exact: true };
 
-            update_parents(evaluated_boards, &parent_board, root_board, screen)
+        // Deepening fills the table with better move orders as it goes, same as the analysis.
+        let mut best_move = None;
+        for depth in 1..=depth {
+            let Some(result) = search.search_root(board, player, depth)
+                else { break };
+            best_move = result.best_move.or(best_move);
         }
+
+        drop(done);
+        best_move
+    })
+}
+
+// What a search of the root came up with, for the player to move.
+struct RootResult {
+    score: i32,
+    best_move: Option<Move>,
+    moves: Vec<(Move, i32)>,  // Every move searched, with its score
+}
+
+struct Search<'a, 'b> {
+    table: &'a TranspositionTable,
+    link: Option<&'a mut Link<'b>>,  // Only the main search has one, helpers go by counters.stop
+    counters: &'a Counters,
+    nodes: u64,  // Not yet added to the counters
+    evaluator: &'a dyn Evaluator,
+    root_moves: Option<&'a [Move]>,  // Only these are worth searching at the root
+    helper: usize,  // 0 for the main search
+    exact: bool,  // Only take scores from entries exactly as deep, deeper ones just order moves
+}
+
+impl Search<'_, '_> {
+    // Checks in with the outside world every so often. True if the current search
+    // should be abandoned.
+    fn interrupted(&mut self) -> bool {
+        let stopped = match &self.link {
+            Some(link) => link.abandoned(),
+            None => self.counters.stop.load(Relaxed),
+        };
+        if stopped {
+            return true;
+        }
+
+        if self.nodes < 4096 {
+            return false;
+        }
+
+        self.flush_nodes();
+        self.link.as_mut().is_some_and(|link| link.poll())
+    }
+
+    fn flush_nodes(&mut self) {
+        self.counters.nodes.fetch_add(self.nodes, Relaxed);
+        self.nodes = 0;
+    }
+
+    // Moves to try, with the table's best move (if any) up front. Center columns take
+    // part in more lines, so trying them next gets better cutoffs.
+    fn move_order(&self, board: &Board) -> impl Iterator<Item = Move> + use<> {
+        let geometry = board.geometry();
+        let (key, flipped) = table_key(board);
+        let first = self.table.get(key)
+            .and_then(|entry| Move::from_index(entry.best_move as i32))
+            .map(|m| orient(board, m, flipped));
+
+        // In a symmetric position, a move and its mirror are the same move.
+        let symmetric = board.mirrored() == *board;
+
+        first.into_iter()
+            .chain(board.moves().filter(move |m| Some(*m) != first))
+            .filter(move |m| !symmetric || m.col() <= geometry.mirror_col(m.col()))
+    }
+
+    // None if the search was interrupted. Every move gets a full window, so each score is
+    // exact rather than a bound. That costs cutoffs at the root, but they all get shown.
+    fn search_root(&mut self, root: &Board, player: Player, depth: i32) -> Option<RootResult> {
+        let mut alpha = -INFINITY;
+        let mut best_move = None;
+        let mut scores = vec![];
+
+        // Last iteration's best move goes first, it is likely still the best. Helpers each
+        // start somewhere else, so they don't all fill the table with the same thing.
+        let mut moves: Vec<Move> = self.move_order(root)
+            .filter(|m| self.root_moves.is_none_or(|only| only.contains(m)))
+            .collect();
+        let count = moves.len();
+        if count > 0 {
+            moves.rotate_left(self.helper % count);
+        }
+
+        for m in moves {
+            let Ok(child) = root.play_move(m, player, false)
+                else { continue };
+
+            let score = -self.negamax(&child, player.opponent(), depth - 1, -INFINITY, INFINITY, 1);
+
+            if self.interrupted() {
+                self.flush_nodes();
+                return None;
+            }
+
+            scores.push((m, score));
+
+            if score > alpha || best_move.is_none() {
+                alpha = score;
+                best_move = Some(m);
+            }
+        }
+
+        let (key, flipped) = table_key(root);
+        self.table.put(Entry::new(key, alpha, depth, Bound::Exact, table_move(root, best_move, flipped), root.pieces_played()));
+        self.flush_nodes();
+
+        Some(RootResult { score: alpha, best_move, moves: scores })
+    }
+
+    fn negamax(&mut self, board: &Board, player: Player, depth: i32, mut alpha: i32, mut beta: i32, ply: i32) -> i32 {
+        self.nodes += 1;
+
+        if depth == 0 || board.next_to_move().is_none() {
+            return evaluate(self.evaluator, board, player, ply);
+        }
+
+        let original_alpha = alpha;
+        let (key, flipped) = table_key(board);
+
+        if let Some(entry) = self.table.get(key) {
+            if entry.depth as i32 == depth || (!self.exact && entry.depth as i32 >= depth) {
+                let score = from_table(entry.score, ply, self.evaluator.weights().win);
+
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower => alpha = alpha.max(score),
+                    Bound::Upper => beta = beta.min(score),
+                }
+
+                if alpha >= beta {
+                    return score;
+                }
+            }
+        }
+
+        let mut best = -INFINITY;
+        let mut best_move = None;
+
+        for m in self.move_order(board) {
+            let Ok(child) = board.play_move(m, player, false)
+                else { continue };
+
+            let score = -self.negamax(&child, player.opponent(), depth - 1, -beta, -alpha, ply + 1);
+
+            if self.interrupted() {
+                return 0;  // Thrown away anyways
+            }
+
+            if score > best {
+                best = score;
+                best_move = Some(m);
+            }
+            alpha = alpha.max(score);
+
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best <= original_alpha {
+            Bound::Upper
+        }
+        else if best >= beta {
+            Bound::Lower
+        }
+        else {
+            Bound::Exact
+        };
+
+        let score = to_table(best, ply, self.evaluator.weights().win);
+        self.table.put(Entry::new(key, score, depth, bound, table_move(board, best_move, flipped), board.pieces_played()));
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval;
+
+    #[test]
+    fn lines_follow_the_table() {
+        // Red with two on the bottom row and room either side. 3 or 6 makes three in a row
+        // open at both ends.
+        let board = Board::from_moves("4455").unwrap();
+        let table = TranspositionTable::with_memory(16);
+        let counters = Counters::default();
+        let mut search = Search { table: &table, link: None, counters: &counters, nodes: 0, evaluator: eval::DEFAULT, root_moves: None, helper: 0, exact: false };
+
+        let result = search.search_root(&board, Player::Red, 5).unwrap();
+        let win = eval::DEFAULT.weights().win;
+        let scores: Vec<(Move, MoveScore)> = result.moves.iter().map(|&(m, score)| (m, move_score(Player::Red, score, win))).collect();
+
+        let lines = best_lines(&table, &board, &scores, 3, 5);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].score, MoveScore::Solved(Solution { winner: Some(Player::Red), plies: 3, best_move: None }));
+        assert!(matches!(lines[0].moves[0], Move::Drop(2 | 5)));
+        assert!(lines.windows(2).all(|pair| pair[0].score.rank(Player::Red) >= pair[1].score.rank(Player::Red)));
+
+        // The line plays out to the win it promises.
+        let mut end = board.clone();
+        for &m in &lines[0].moves {
+            end = end.play_move(m, end.next_to_move().unwrap(), true).unwrap();
+        }
+        assert_eq!((lines[0].moves.len(), end.winner()), (3, Some(Player::Red)));
+    }
+
+    #[test]
+    fn prunes_what_cant_come_up() {
+        let table = TranspositionTable::with_memory(16);
+        let counters = Counters::default();
+        let mut search = Search { table: &table, link: None, counters: &counters, nodes: 0, evaluator: eval::DEFAULT, root_moves: None, helper: 0, exact: false };
+        search.search_root(&Board::from_moves("44").unwrap(), Player::Red, 5).unwrap();
+
+        // Siblings of the new root go, not just positions with fewer pieces.
+        let root = Board::from_moves("4453").unwrap();
+        let sibling = Board::from_moves("4456").unwrap();
+        assert!(table.get(table_key(&sibling).0).is_some());
+        assert!(prune(&table, &root) > 0);
+        assert!(table.get(table_key(&sibling).0).is_none());
+
+        // Emptied to count what was left, checking each on the way out.
+        let kept = table.retain(|entry| {
+            let board = root.board_for_key(entry.key).unwrap();
+            assert!(root.can_reach(&board) || root.mirrored().can_reach(&board), "{}", board.display());
+            false
+        });
+        assert!(kept > 0);
+    }
+
+    #[test]
+    fn depth_limits_ignore_deeper_entries() {
+        for moves in ["", "4455", "3344", "121"] {
+            let board = Board::from_moves(moves).unwrap();
+            let fresh = TranspositionTable::with_memory(16);
+            let expected = fixed_depth_move(&board, eval::DEFAULT, 2, &fresh, None);
+
+            // Whatever an earlier, deeper search left behind, and with a clock running.
+            let table = TranspositionTable::with_memory(16);
+            let counters = Counters::default();
+            let mut search = Search { table: &table, link: None, counters: &counters, nodes: 0, evaluator: eval::DEFAULT, root_moves: None, helper: 0, exact: false };
+            search.search_root(&board, board.next_to_move().unwrap(), 7).unwrap();
+
+            let deadline = time::Instant::now() + time::Duration::from_secs(60);
+            assert_eq!(fixed_depth_move(&board, eval::DEFAULT, 2, &table, Some(deadline)), expected, "{moves}");
+        }
+    }
+
+    #[test]
+    fn predicts_from_the_last_search() {
+        let board = Board::from_moves("4455").unwrap();
+        let table = TranspositionTable::with_memory(16);
+        let counters = Counters::default();
+        let mut search = Search { table: &table, link: None, counters: &counters, nodes: 0, evaluator: eval::DEFAULT, root_moves: None, helper: 0, exact: false };
+        search.search_root(&board, Player::Red, 5).unwrap();
+
+        // Straight out of the table, and nothing for positions it never saw.
+        assert_eq!(predict(&table, None, &board), principal_variation(&table, board.clone(), 1).first().copied());
+        assert!(matches!(predict(&table, None, &board), Some(Move::Drop(2 | 5))));
+        assert_eq!(predict(&table, None, &Board::from_moves("1").unwrap()), None);
+
+        // Monte Carlo's opinion comes first when there's a tree.
+        let mut tree = Tree::new(Board::from_moves("12121").unwrap(), None, Playout::Random, 100_000, 1);
+        tree.run(3000);
+        assert_eq!(predict(&table, Some(&tree), &Board::from_moves("12121").unwrap()), Some(Move::Drop(0)));
+        assert!(matches!(predict(&table, Some(&tree), &board), Some(Move::Drop(2 | 5))));
     }
 }
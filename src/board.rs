@@ -1,3 +1,6 @@
+use std::{fmt, hash::{Hash, Hasher}, sync::Mutex};
+
+use crate::{eval, rules::{Rules, Standard}};
 
 #[derive(Hash, Clone, Copy, Eq, PartialEq, Debug)]
 pub enum Player {
@@ -20,28 +23,605 @@ pub enum Tile {
     Piece(Player),
 }
 
-#[derive(Hash, Clone, Eq, PartialEq, Debug)]
+// Move strings use one character per column, and each column takes height + 1 bits of a
+// 128 bit board.
+pub const MAX_WIDTH: i32 = 9;
+pub const MAX_HEIGHT: i32 = 12;
+pub const MAX_CONNECT: i32 = if MAX_WIDTH > MAX_HEIGHT { MAX_WIDTH } else { MAX_HEIGHT };
+
+// Board size and how many in a row it takes to win.
+#[derive(Hash, Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Dimensions {
+    pub width: i32,
+    pub height: i32,
+    pub connect: i32,
+}
+
+impl Dimensions {
+    pub const STANDARD: Dimensions = Dimensions { width: 7, height: 6, connect: 4 };
+
+    pub fn new(width: i32, height: i32, connect: i32) -> Result<Dimensions, String> {
+        if !(2..=MAX_WIDTH).contains(&width) || !(2..=MAX_HEIGHT).contains(&height) {
+            return Err(format!("Bad board size: {width}x{height} (up to {MAX_WIDTH}x{MAX_HEIGHT})"));
+        }
+        if connect < 2 || connect > width.max(height) {
+            return Err(format!("Can't connect {connect} on a {width}x{height} board"));
+        }
+
+        Ok(Dimensions { width, height, connect })
+    }
+
+    pub fn cells(&self) -> i32 {
+        self.width * self.height
+    }
+}
+
+impl Default for Dimensions {
+    fn default() -> Dimensions {
+        Dimensions::STANDARD
+    }
+}
+
+impl fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}, connect {}", self.width, self.height, self.connect)
+    }
+}
+
+pub type Bits = u128;
+
+// Shifts that run off the end of the board give nothing, rather than overflowing. Long
+// lines on tall boards can ask for that.
+pub fn shr(bits: Bits, n: i32) -> Bits {
+    bits.checked_shr(n as u32).unwrap_or(0)
+}
+
+pub fn shl(bits: Bits, n: i32) -> Bits {
+    bits.checked_shl(n as u32).unwrap_or(0)
+}
+
+// Table keys are 64 bits. Bitboard keys that fit in 63 are used as they are, bigger boards
+// get hashed down. The top bit is always left clear for the solver to tag its keys with.
+pub fn fold_key(key: Bits) -> u64 {
+    if key >> 63 == 0 {
+        return key as u64;
+    }
+
+    let mut z = (key as u64) ^ ((key >> 64) as u64).wrapping_mul(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (z ^ (z >> 31)) >> 1
+}
+
+// Everything about the board's shape, worked out once per size.
+//
+// Each column gets height + 1 bits, bit (col * col_bits + row), row 0 is bottom. The extra
+// bit on top of each column is always empty, so shifted lines can't bleed from the top of
+// one column into the bottom of the next.
+pub struct Geometry {
+    dimensions: Dimensions,
+    rules: &'static dyn Rules,
+    col_bits: i32,
+    bottom: Bits,  // Bottom cell of every column
+    board_mask: Bits,  // Every real cell
+    windows: Vec<Bits>,  // Every line of `connect` cells, used for the heuristic
+    wrapped: Vec<Bits>,  // The windows that go round the edge, which shifting can't find
+    column_order: Vec<i32>,
+    moves: Vec<Move>,  // Every move the rules have, left to right
+    move_order: Vec<Move>,  // The same, center columns first
+}
+
+// Boards only carry a pointer, so they stay cheap to copy around. Each size and rule set is
+// built the first time it's asked for, and kept for good.
+fn geometry(dimensions: Dimensions, rules: &'static dyn Rules) -> &'static Geometry {
+    static GEOMETRIES: Mutex<Vec<&'static Geometry>> = Mutex::new(Vec::new());
+
+    let mut geometries = GEOMETRIES.lock().unwrap();
+    if let Some(geometry) = geometries.iter().find(|g| g.dimensions == dimensions && *g.rules == *rules) {
+        return geometry;
+    }
+
+    let geometry = Box::leak(Box::new(Geometry::new(dimensions, rules)));
+    geometries.push(geometry);
+    geometry
+}
+
+impl Geometry {
+    fn new(dimensions: Dimensions, rules: &'static dyn Rules) -> Geometry {
+        let Dimensions { width, height, connect } = dimensions;
+
+        let mut geometry = Geometry {
+            dimensions,
+            rules,
+            col_bits: height + 1,
+            bottom: 0,
+            board_mask: 0,
+            windows: vec![],
+            wrapped: vec![],
+            column_order: (0..width).collect(),
+            moves: vec![],
+            move_order: vec![],
+        };
+
+        for col in 0..width {
+            geometry.bottom |= geometry.bottom_mask(col);
+            geometry.board_mask |= geometry.column_mask(col);
+        }
+
+        // (d_r, d_c) for up, right, up-right, down-right
+        for (d_r, d_c) in [(1, 0), (0, 1), (1, 1), (-1, 1)] {
+            for row in 0..height {
+                for col in 0..width {
+                    let end_row = row + (connect - 1) * d_r;
+                    let end_col = col + (connect - 1) * d_c;
+
+                    if end_row < 0 || end_row >= height {
+                        continue;
+                    }
+
+                    let wraps = end_col >= width;
+                    if wraps && !rules.wraps() {
+                        continue;
+                    }
+
+                    let window = (0..connect)
+                        .map(|i| geometry.cell_mask(row + i * d_r, (col + i * d_c) % width))
+                        .fold(0, |window, cell| window | cell);
+
+                    // A row shorter than the line would have to use a cell twice. And a row
+                    // exactly as long gives the same window from every starting column.
+                    if window.count_ones() as i32 != connect || geometry.windows.contains(&window) {
+                        continue;
+                    }
+
+                    geometry.windows.push(window);
+                    if wraps {
+                        geometry.wrapped.push(window);
+                    }
+                }
+            }
+        }
+
+        // Center columns take part in more lines, so they go first. Ties go left to right.
+        geometry.moves = rules.moves(&geometry.column_order);
+        geometry.column_order.sort_by_key(|col| ((2 * col - (width - 1)).abs(), *col));
+        geometry.move_order = rules.moves(&geometry.column_order);
+
+        geometry
+    }
+
+    pub fn width(&self) -> i32 {
+        self.dimensions.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.dimensions.height
+    }
+
+    pub fn connect(&self) -> i32 {
+        self.dimensions.connect
+    }
+
+    pub fn cells(&self) -> i32 {
+        self.dimensions.cells()
+    }
+
+    pub fn bottom(&self) -> Bits {
+        self.bottom
+    }
+
+    pub fn board_mask(&self) -> Bits {
+        self.board_mask
+    }
+
+    // Center first, for move ordering.
+    pub fn column_order(&self) -> &[i32] {
+        &self.column_order
+    }
+
+    // Every line of `connect` cells.
+    pub fn windows(&self) -> &[Bits] {
+        &self.windows
+    }
+
+    pub fn row_mask(&self, row: i32) -> Bits {
+        self.bottom << row
+    }
+
+    pub fn bottom_mask(&self, col: i32) -> Bits {
+        1 << (col * self.col_bits)
+    }
+
+    pub fn top_mask(&self, col: i32) -> Bits {
+        1 << (self.height() - 1 + col * self.col_bits)
+    }
+
+    pub fn column_mask(&self, col: i32) -> Bits {
+        ((1 << self.height()) - 1) << (col * self.col_bits)
+    }
+
+    fn cell_mask(&self, row: i32, col: i32) -> Bits {
+        1 << (col * self.col_bits + row)
+    }
+
+    pub fn mirror_col(&self, col: i32) -> i32 {
+        self.width() - 1 - col
+    }
+
+    // Flips a bitboard left to right. Spare bits move along with their column.
+    pub fn mirror_bits(&self, bits: Bits) -> Bits {
+        let mut mirrored = 0;
+        for col in 0..self.width() {
+            let column = (bits >> (col * self.col_bits)) & ((1 << self.col_bits) - 1);
+            mirrored |= column << (self.mirror_col(col) * self.col_bits);
+        }
+        mirrored
+    }
+
+    // Shift amounts for vertical, horizontal, and both diagonals.
+    pub fn directions(&self) -> [i32; 4] {
+        [1, self.col_bits, self.col_bits - 1, self.col_bits + 1]
+    }
+
+    // Whether `pieces` has `connect` in a row anywhere.
+    pub fn has_line(&self, pieces: Bits) -> bool {
+        let connect = self.connect();
+
+        for d in self.directions() {
+            // Runs of `run` in a row, doubling up as far as it goes, then topped up to
+            // the full length.
+            let mut runs = pieces;
+            let mut run = 1;
+            while run * 2 <= connect {
+                runs &= shr(runs, run * d);
+                run *= 2;
+            }
+            runs &= shr(runs, (connect - run) * d);
+
+            if runs != 0 {
+                return true;
+            }
+        }
+
+        self.wrapped.iter().any(|line| pieces & line == *line)
+    }
+}
+
+// Only the size and rules matter, the rest follows from them.
+impl fmt::Debug for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Geometry({:?}, {:?})", self.dimensions, self.rules)
+    }
+}
+
+#[derive(Hash, Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Move {
+    Drop (i32),  // Column, 0 based
+    Pop (i32),  // Take your own piece off the bottom of the column, PopOut only
+}
+
+impl Move {
+    pub fn col(&self) -> i32 {
+        match self {
+            Move::Drop(col) | Move::Pop(col) => *col,
+        }
+    }
+
+    pub fn mirrored(&self, geometry: &Geometry) -> Move {
+        match self {
+            Move::Drop(col) => Move::Drop(geometry.mirror_col(*col)),
+            Move::Pop(col) => Move::Pop(geometry.mirror_col(*col)),
+        }
+    }
+
+    // Small non-negative number for storing in the table. Doesn't depend on the board size.
+    pub fn to_index(self) -> i32 {
+        match self {
+            Move::Drop(col) => col,
+            Move::Pop(col) => MAX_WIDTH + col,
+        }
+    }
+
+    pub fn from_index(index: i32) -> Option<Move> {
+        match index {
+            0.. if index < MAX_WIDTH => Some(Move::Drop(index)),
+            0.. if index < 2 * MAX_WIDTH => Some(Move::Pop(index - MAX_WIDTH)),
+            _ => None,
+        }
+    }
+}
+
+// As typed and in move strings: "4" drops in column 4, "p4" pops it.
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Move::Drop(col) => write!(f, "{}", col + 1),
+            Move::Pop(col) => write!(f, "p{}", col + 1),
+        }
+    }
+}
+
+// Why a move can't be played.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveError {
+    WrongPlayer (Player),  // The player who tried to move out of turn
+    ColumnOutOfRange (i32),
+    ColumnFull (i32),
+    NotYourPiece (i32),  // Popping a column whose bottom piece is someone else's, or nobody's
+    PopNotAllowed,
+    GameOver,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {  // Columns are shown 1 based, like the labels
+            MoveError::WrongPlayer(player) => write!(f, "It's not {player:?}'s turn"),
+            MoveError::ColumnOutOfRange(col) => write!(f, "There is no column {}", col + 1),
+            MoveError::ColumnFull(col) => write!(f, "Column {} is full", col + 1),
+            MoveError::NotYourPiece(col) => write!(f, "The bottom of column {} isn't yours to pop", col + 1),
+            MoveError::PopNotAllowed => write!(f, "Pops are only allowed in PopOut"),
+            MoveError::GameOver => write!(f, "Game is over"),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+// A move string that didn't work out, and which move broke it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SequenceError {
+    NotAColumn { ply: usize, character: char },
+    IllegalMove { ply: usize, error: MoveError },
+}
+
+impl SequenceError {
+    // 1 based
+    pub fn ply(&self) -> usize {
+        match self {
+            SequenceError::NotAColumn { ply, .. } => *ply,
+            SequenceError::IllegalMove { ply, .. } => *ply,
+        }
+    }
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceError::NotAColumn { ply, character } => write!(f, "Move {ply}: '{character}' is not a column"),
+            SequenceError::IllegalMove { ply, error } => write!(f, "Move {ply}: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for SequenceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SequenceError::NotAColumn { .. } => None,
+            SequenceError::IllegalMove { error, .. } => Some(error),
+        }
+    }
+}
+
+// Move strings list the columns played, 1 based like the labels, one character per move:
+// "4453214". Pops get a p in front, "44p4". Whitespace is ignored.
+pub fn parse_moves(moves: &str, width: i32) -> Result<Vec<Move>, SequenceError> {
+    let mut parsed = vec![];
+    let mut pop = false;
+
+    for c in moves.chars().filter(|c| !c.is_whitespace()) {
+        let ply = parsed.len() + 1;
+
+        if c == 'p' && !pop {
+            pop = true;
+            continue;
+        }
+
+        let col = match c.to_digit(10) {
+            Some(col) if (1..=width as u32).contains(&col) => col as i32 - 1,
+            _ => return Err(SequenceError::NotAColumn { ply, character: c }),
+        };
+
+        parsed.push(if pop { Move::Pop(col) } else { Move::Drop(col) });
+        pop = false;
+    }
+
+    if pop {
+        return Err(SequenceError::NotAColumn { ply: parsed.len() + 1, character: 'p' });
+    }
+
+    Ok(parsed)
+}
+
+pub fn moves_to_string(moves: &[Move]) -> String {
+    moves.iter().map(|m| m.to_string()).collect()
+}
+
+// Where the `ply`th move (1 based) starts in a move string without whitespace. For
+// pointing at the move a SequenceError is about.
+pub fn move_offset(moves: &str, ply: usize) -> usize {
+    let mut seen = 0;
+
+    for (i, c) in moves.char_indices() {
+        if seen + 1 == ply {
+            return i;
+        }
+        if c != 'p' {
+            seen += 1;
+        }
+    }
+
+    moves.len()
+}
+
+#[derive(Clone, Debug)]
 pub struct Board {
-    pub tiles: [[Tile; 7]; 6], // row 0 is bottom!
+    red: Bits,
+    yellow: Bits,
+    to_move: Player,  // Whose turn it is, unless the game is over
+    geometry: &'static Geometry,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum AnalyzedTile {
-    Empty,
-    You,
-    Enemy
+// Boards of different sizes or rules are never equal, even if both are empty.
+impl PartialEq for Board {
+    fn eq(&self, other: &Board) -> bool {
+        self.red == other.red && self.yellow == other.yellow && self.to_move == other.to_move
+            && std::ptr::eq(self.geometry, other.geometry)
+    }
+}
+
+impl Eq for Board {}
+
+impl Hash for Board {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.red.hash(state);
+        self.yellow.hash(state);
+        self.to_move.hash(state);
+        self.dimensions().hash(state);
+        self.rules().name().hash(state);
+    }
 }
 
 impl Board {
+    // Standard 7x6 connect 4.
     pub fn new() -> Board {
-        Board {tiles: [[Tile::Empty; 7]; 6]}
+        Board::empty(Dimensions::STANDARD, &Standard)
+    }
+
+    pub fn empty(dimensions: Dimensions, rules: &'static dyn Rules) -> Board {
+        Board {red: 0, yellow: 0, to_move: Player::Red, geometry: geometry(dimensions, rules)}
+    }
+
+    // Standard board after playing the move string from the start.
+    #[allow(unused)]
+    pub fn from_moves(moves: &str) -> Result<Board, SequenceError> {
+        let mut board = Board::new();
+
+        for (i, m) in parse_moves(moves, board.dimensions().width)?.into_iter().enumerate() {
+            let Some(player) = board.next_to_move()
+                else { return Err(SequenceError::IllegalMove { ply: i + 1, error: MoveError::GameOver }) };
+
+            board = board.play_move(m, player, true)
+                .map_err(|error| SequenceError::IllegalMove { ply: i + 1, error })?;
+        }
+
+        Ok(board)
+    }
+
+    pub fn geometry(&self) -> &'static Geometry {
+        self.geometry
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        self.geometry.dimensions
+    }
+
+    pub fn rules(&self) -> &'static dyn Rules {
+        self.geometry.rules
+    }
+
+    fn occupied(&self) -> Bits {
+        self.red | self.yellow
+    }
+
+    pub fn pieces(&self, player: Player) -> Bits {
+        match player {
+            Player::Red => self.red,
+            Player::Yellow => self.yellow,
+        }
+    }
+
+    fn pieces_mut(&mut self, player: Player) -> &mut Bits {
+        match player {
+            Player::Red => &mut self.red,
+            Player::Yellow => &mut self.yellow,
+        }
+    }
+
+    // Unique per position. In a column with h pieces, occupied + red lands somewhere in
+    // [2^h - 1, 2^(h+1) - 2], which no other height can produce, and it never carries
+    // into the next column thanks to the spare bit. Big boards get it hashed, see fold_key.
+    pub fn key(&self) -> u64 {
+        let mut key = self.red + self.occupied();
+
+        // Under some rules (pops) the piece count doesn't say whose turn it is, so that gets
+        // the bit past the last column.
+        if self.to_move == Player::Yellow {
+            key |= 1 << (self.dimensions().width * self.geometry.col_bits);
+        }
+
+        fold_key(key)
+    }
+
+    // Undoes key, for a board of the same shape and rules as this one. None for keys that
+    // aren't one of those boards' keys, like solver keys, and on boards big enough to get
+    // their keys hashed, where there's no telling.
+    pub fn board_for_key(&self, key: u64) -> Option<Board> {
+        let col_bits = self.geometry.col_bits;
+        let width = self.dimensions().width;
+        if width * col_bits >= 63 {
+            return None;
+        }
+
+        let mut board = Board::empty(self.dimensions(), self.rules());
+        if (key as Bits) >> (width * col_bits) & 1 == 1 {
+            board.to_move = Player::Yellow;
+        }
+
+        for col in 0..width {
+            // A column with h pieces holds 2^h - 1 + red, so the height is where the top bit is.
+            let column = (key as Bits >> (col * col_bits)) & ((1 << col_bits) - 1);
+            let height = (column + 1).ilog2();
+            if height as i32 > self.dimensions().height {
+                return None;
+            }
+            let occupied = (1 << height) - 1;
+            board.red |= (column - occupied) << (col * col_bits);
+            board.yellow |= (occupied & !(column - occupied)) << (col * col_bits);
+        }
+
+        // Whatever doesn't round trip wasn't a key of ours.
+        Some(board).filter(|board| board.key() == key)
+    }
+
+    // The same position seen in a mirror. Plays out exactly the same, with columns flipped.
+    pub fn mirrored(&self) -> Board {
+        Board {
+            red: self.geometry.mirror_bits(self.red),
+            yellow: self.geometry.mirror_bits(self.yellow),
+            ..*self
+        }
     }
 
-    fn display_row(&self, row: usize) -> String {
+    // Picks one of the board and its mirror image, so both map to the same position.
+    pub fn canonical(&self) -> Board {
+        let mirrored = self.mirrored();
+        if mirrored.key() < self.key() {
+            mirrored
+        }
+        else {
+            self.clone()
+        }
+    }
+
+    pub fn tile(&self, row: i32, col: i32) -> Tile {
+        let cell = self.geometry.cell_mask(row, col);
+
+        if self.red & cell != 0 {
+            Tile::Piece(Player::Red)
+        }
+        else if self.yellow & cell != 0 {
+            Tile::Piece(Player::Yellow)
+        }
+        else {
+            Tile::Empty
+        }
+    }
+
+    fn display_row(&self, row: i32) -> String {
         let mut string  = "|".to_string();
 
-        for i in 0..7 {
-            string += match self.tiles[row][i] {
+        for i in 0..self.dimensions().width {
+            string += match self.tile(row, i) {
                 Tile::Empty => " . ",
                 Tile::Piece(Player::Red) => " R ",
                 Tile::Piece(Player::Yellow) => " Y ",
@@ -54,296 +634,818 @@ impl Board {
     }
 
     pub fn display(&self) -> String {
+        let Dimensions { width, height, .. } = self.dimensions();
+        let edge = format!("+{}+\n", " - ".repeat(width as usize));
+
         let mut string = "".to_string();
-        string += "+ -  -  -  -  -  -  - +\n";
+        string += &edge;
 
-        for i in (0..6).rev() {
+        for i in (0..height).rev() {
             string += self.display_row(i).as_str();
         }
 
-        string += "+ -  -  -  -  -  -  - +\n";
-        string += "  1  2  3  4  5  6  7  "; // user facing labels
+        string += &edge;
+
+        // user facing labels
+        string += " ";
+        for i in 1..=width {
+            string += &format!(" {i} ");
+        }
+        string += " ";
 
         string
     }
 
-    fn in_bounds(row: i32, col: i32) -> bool {
-        row >= 0 && col >= 0 && row < 6 && col < 7
+    fn in_bounds(&self, row: i32, col: i32) -> bool {
+        row >= 0 && col >= 0 && row < self.dimensions().height && col < self.dimensions().width
     }
 
-    // Errors on wrong player or illegal move.
-    pub fn play(&self, col: i32, player: Player, validate_player: bool) -> Result<Board, String> {
-        if validate_player == true && Some(player) != self.next_to_move() {
-            return Err("Bad player".into());
-        }
-        
-        if !Self::in_bounds(0, col) {
-            return Err("Illegal coordinate".into());
+    // Errors on wrong player or illegal move. Without validation, it's up to the caller
+    // not to play on after the game is over.
+    pub fn play_move(&self, m: Move, player: Player, validate_player: bool) -> Result<Board, MoveError> {
+        if validate_player {
+            match self.next_to_move() {
+                None => return Err(MoveError::GameOver),
+                Some(p) if p != player => return Err(MoveError::WrongPlayer(player)),
+                _ => (),
+            }
         }
 
-        let mut i = 5;
-        while Self::in_bounds(i, col) && self.tiles[i as usize][col as usize] == Tile::Empty {
-            i -= 1;
+        self.rules().apply(self, m, player)
+    }
+
+    #[allow(unused)]
+    pub fn play(&self, col: i32, player: Player, validate_player: bool) -> Result<Board, MoveError> {
+        self.play_move(Move::Drop(col), player, validate_player)
+    }
+
+    #[allow(unused)]
+    pub fn pop(&self, col: i32, player: Player, validate_player: bool) -> Result<Board, MoveError> {
+        self.play_move(Move::Pop(col), player, validate_player)
+    }
+
+    // The pieces of a move, for rules to build on. These don't care whose turn it is, or
+    // whether the game is over.
+
+    // Drops `player`'s piece in the column, and hands the turn over.
+    pub fn drop_piece(&self, col: i32, player: Player) -> Result<Board, MoveError> {
+        if !self.in_bounds(0, col) {
+            return Err(MoveError::ColumnOutOfRange(col));
         }
 
-        i += 1;
+        // Adding the bottom bit carries up through the filled cells to the first empty one.
+        let cell = (self.occupied() + self.geometry.bottom_mask(col)) & self.geometry.column_mask(col);
 
-        if i > 5 {
-            return Err("Column full".into());
+        if cell == 0 {
+            return Err(MoveError::ColumnFull(col));
         }
 
         let mut new_board = self.clone();
 
-        new_board.tiles[i as usize][col as usize] = Tile::Piece(player);
+        *new_board.pieces_mut(player) |= cell;
+        new_board.to_move = player.opponent();
 
         Ok(new_board)
     }
 
-    fn win_on_chain(&self, mut row: i32, mut col: i32, d_r: i32, d_c: i32) -> Option<Player> {
-        let mut red = 0;
-        let mut yellow = 0;
-        while Self::in_bounds(row, col) {
-            match self.tiles[row as usize][col as usize] {
-                Tile::Empty => {
-                    red = 0;
-                    yellow = 0;
-                }
-                Tile::Piece(Player::Red) => {
-                    red += 1;
-                    yellow = 0;
-                    if red == 4 {
-                        return Some(Player::Red);
-                    }
-                }
-                Tile::Piece(Player::Yellow) => {
-                    red = 0;
-                    yellow += 1;
-                    if yellow == 4 {
-                        return Some(Player::Yellow);
-                    }
-                }
-            }
+    // Takes `player`'s piece off the bottom of the column, and everything above drops down
+    // one.
+    pub fn pop_piece(&self, col: i32, player: Player) -> Result<Board, MoveError> {
+        if !self.in_bounds(0, col) {
+            return Err(MoveError::ColumnOutOfRange(col));
+        }
+
+        let bottom = self.geometry.bottom_mask(col);
+        if self.pieces(player) & bottom == 0 {
+            return Err(MoveError::NotYourPiece(col));
+        }
+
+        let column = self.geometry.column_mask(col);
+        let drop_down = |bits: Bits| (bits & !column) | ((bits & column & !bottom) >> 1);
+
+        Ok(Board {
+            red: drop_down(self.red),
+            yellow: drop_down(self.yellow),
+            to_move: player.opponent(),
+            ..*self
+        })
+    }
+
+    // Undoes `drop_piece`: takes the top piece off the column if it's `player`'s, and makes
+    // it their turn again.
+    pub fn take_top(&self, col: i32, player: Player) -> Option<Board> {
+        let column = self.occupied() & self.geometry.column_mask(col);
+        if column == 0 {
+            return None;
+        }
+
+        // Highest set bit in the column is the top piece.
+        let top = 1 << (Bits::BITS - 1 - column.leading_zeros());
+        if self.pieces(player) & top == 0 {
+            return None;
+        }
+
+        let mut new_board = self.clone();
+
+        *new_board.pieces_mut(player) &= !top;
+        new_board.to_move = player;
+
+        Some(new_board)
+    }
+
+    // Undoes `pop_piece`: pushes `player`'s piece back in at the bottom, lifting the column.
+    pub fn push_bottom(&self, col: i32, player: Player) -> Option<Board> {
+        if self.occupied() & self.geometry.top_mask(col) != 0 {
+            return None;
+        }
+
+        let column = self.geometry.column_mask(col);
+        let lift = |bits: Bits| (bits & !column) | ((bits & column) << 1);
 
-            row += d_r;
-            col += d_c;
+        let mut new_board = Board {
+            red: lift(self.red),
+            yellow: lift(self.yellow),
+            to_move: player,
+            ..*self
+        };
+        *new_board.pieces_mut(player) |= self.geometry.bottom_mask(col);
+
+        Some(new_board)
+    }
+
+    pub fn has_line(&self, player: Player) -> bool {
+        self.geometry.has_line(self.pieces(player))
+    }
+
+    // The cell each column's next piece would land in. Nothing for full columns.
+    pub fn playable(&self) -> Bits {
+        (self.occupied() + self.geometry.bottom) & self.geometry.board_mask
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.occupied() == self.geometry.board_mask
+    }
+
+    // Whose turn it would be, whether or not the game is over.
+    pub fn turn(&self) -> Player {
+        self.to_move
+    }
+
+    // Every move the rules have, center columns first. Not all of them have to be legal.
+    pub fn moves(&self) -> impl Iterator<Item = Move> + use<> {
+        self.geometry.move_order.iter().copied()
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let Some(player) = self.next_to_move()
+            else { return vec![] };
+
+        self.moves().filter(|m| self.play_move(*m, player, false).is_ok()).collect()
+    }
+
+    // Moves that win on the spot for `player`, whether or not it's their turn. With the
+    // other player to move, these are what they have to stop.
+    pub fn winning_moves(&self, player: Player) -> Vec<Move> {
+        let mut board = self.clone();
+        board.to_move = player;
+
+        if board.next_to_move().is_none() {
+            return vec![];
         }
 
-        None
+        board.moves()
+            .filter(|m| board.play_move(*m, player, false).is_ok_and(|next| next.winner() == Some(player)))
+            .collect()
     }
 
-    fn win_chains() -> [(i32, i32, i32, i32); 25] {
-        [ (0, 0, 0, 1), (1, 0, 0, 1), (2, 0, 0, 1), (3, 0, 0, 1), (4, 0, 0, 1), (5, 0, 0, 1)  // rows
-        , (0, 0, 1, 0),  (0, 1, 1, 0),  (0, 2, 1, 0),  (0, 3, 1, 0),  (0, 4, 1, 0),  (0, 5, 1, 0), (0, 6, 1, 0)  // columns 
-        , (3, 0, -1, 1), (4, 0, -1, 1), (5, 0, -1, 1), (5, 1, -1, 1), (5, 2, -1, 1), (5, 3, -1, 1)  // downward right
-        , (3, 6, -1, -1), (4, 6, -1, -1), (5, 6, -1, -1), (5, 5, -1, -1), (5, 4, -1, -1), (5, 3, -1, -1)  // downward left
-        ]
+    // The moves the player to move has to pick from: a win if there is one, otherwise
+    // whatever stops the other side winning next move. None if nothing is forced. Empty if
+    // nothing stops them, the game is lost.
+    pub fn forced_moves(&self) -> Option<Vec<Move>> {
+        let player = self.next_to_move()?;
+
+        let wins = self.winning_moves(player);
+        if !wins.is_empty() {
+            return Some(wins);
+        }
+
+        if self.winning_moves(player.opponent()).is_empty() {
+            return None;
+        }
+
+        Some(self.legal_moves().into_iter()
+            .filter(|m| {
+                let next = self.play_move(*m, player, false).expect("legal");
+                next.winning_moves(player.opponent()).is_empty()
+            })
+            .collect())
     }
 
     // None if no winner (or game ongoing)
     pub fn winner(&self) -> Option<Player> {
-        for (row, col, d_r, d_c) in Self::win_chains() {
-            if let Some(p) = self.win_on_chain(row, col, d_r, d_c) {
-                return Some(p)
-            }
+        self.rules().winner(self)
+    }
+
+    // Returns None if the game is over. Repeated positions aren't the board's business,
+    // see Game::outcome.
+    pub fn next_to_move(&self) -> Option<Player> {
+        self.rules().next_to_move(self)
+    }
+
+    // Most plies the game can go on for, None if there's no limit.
+    pub fn plies_left(&self) -> Option<i32> {
+        self.rules().plies_left(self)
+    }
+
+    // Whether `later` could come up in a game that's been through this position. Only ever
+    // true under rules where pieces stay put, pops could lead anywhere.
+    pub fn can_reach(&self, later: &Board) -> bool {
+        std::ptr::eq(self.geometry, later.geometry)
+            && self.plies_left().is_some()
+            && self.red & !later.red == 0
+            && self.yellow & !later.yellow == 0
+    }
+
+    pub fn next_boards(&self) -> Vec<Board> {
+        let Some(player) = self.next_to_move()
+            else { return vec![] };
+
+        self.geometry.moves.iter()
+            .filter_map(|m| self.play_move(*m, player, false).ok())  // validation turned off for speed!
+            .collect()
+    }
+
+    #[allow(unused)]
+    pub fn prev_boards(&self) -> Vec<Board> {
+        if self.next_to_move().is_none() {
+            return vec![];
         }
 
-        None
+        self.geometry.moves.iter()
+            .filter_map(|m| self.rules().undo(self, *m))
+            .collect()
     }
 
-    // May panic if the board is in a bad state. Returns None if the game is over.
-    pub fn next_to_move(&self) -> Option<Player> {
-        if let Some(_) = self.winner() {
-            None
+    pub fn pieces_played(&self) -> i32 {
+        self.occupied().count_ones() as i32
+    }
+
+    // Subjective score by the default evaluator. Positive / High means win better for Red
+    // (player 1). Negative / Low means better for Yellow.
+    pub fn get_score(&self) -> i32 {
+        eval::DEFAULT.score(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    pub use super::*;
+    use crate::rules::{Cylinder, PopOut, Standard};
+
+    #[test]
+    fn prev_board_sane() {
+        let b1 = Board::new();
+
+        let b2 = b1.play(0, Player::Red, true).expect("Works");
+
+        assert!(b1 == b2.prev_boards()[0]);
+
+        let b3 = b2.play(0, Player::Yellow, true).expect("Works");
+
+        assert!(b2 == b3.prev_boards()[0]);
+
+        let b4 = b3.play(0, Player::Red, true).expect("Works");
+        let b5 = b4.play(0, Player::Yellow, true).expect("Works");
+        let b6 = b5.play(0, Player::Red, true).expect("Works");
+        let b7 = b6.play(0, Player::Yellow, true).expect("Works");
+
+        assert!(b6 == b7.prev_boards()[0]);
+    }
+
+    // The original array backed board, kept around as a reference for the bitboard. Pasted
+    // as it was, lints and all, so it checks the bitboard against what it replaced.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum AnalyzedTile {
+        Empty,
+        You,
+        Enemy
+    }
+
+    #[derive(Clone, PartialEq, Debug)]
+    struct ArrayBoard {
+        tiles: [[Tile; 7]; 6], // row 0 is bottom!
+    }
+
+    #[allow(clippy::bool_comparison, clippy::redundant_pattern_matching, clippy::partialeq_to_none, clippy::needless_range_loop)]
+    impl ArrayBoard {
+        fn new() -> ArrayBoard {
+            ArrayBoard {tiles: [[Tile::Empty; 7]; 6]}
         }
-        else {
+
+        fn in_bounds(row: i32, col: i32) -> bool {
+            row >= 0 && col >= 0 && row < 6 && col < 7
+        }
+
+        // Errors on wrong player or illegal move.
+        fn play(&self, col: i32, player: Player, validate_player: bool) -> Result<ArrayBoard, String> {
+            if validate_player == true && Some(player) != self.next_to_move() {
+                return Err("Bad player".into());
+            }
+
+            if !Self::in_bounds(0, col) {
+                return Err("Illegal coordinate".into());
+            }
+
+            let mut i = 5;
+            while Self::in_bounds(i, col) && self.tiles[i as usize][col as usize] == Tile::Empty {
+                i -= 1;
+            }
+
+            i += 1;
+
+            if i > 5 {
+                return Err("Column full".into());
+            }
+
+            let mut new_board = self.clone();
+
+            new_board.tiles[i as usize][col as usize] = Tile::Piece(player);
+
+            Ok(new_board)
+        }
+
+        fn win_on_chain(&self, mut row: i32, mut col: i32, d_r: i32, d_c: i32) -> Option<Player> {
             let mut red = 0;
             let mut yellow = 0;
-
-            for row in self.tiles {
-                for tile in row {
-                    match tile {
-                        Tile::Piece(Player::Red) => red += 1,
-                        Tile::Piece(Player::Yellow) => yellow += 1,
-                        _ => (),
+            while Self::in_bounds(row, col) {
+                match self.tiles[row as usize][col as usize] {
+                    Tile::Empty => {
+                        red = 0;
+                        yellow = 0;
+                    }
+                    Tile::Piece(Player::Red) => {
+                        red += 1;
+                        yellow = 0;
+                        if red == 4 {
+                            return Some(Player::Red);
+                        }
+                    }
+                    Tile::Piece(Player::Yellow) => {
+                        red = 0;
+                        yellow += 1;
+                        if yellow == 4 {
+                            return Some(Player::Yellow);
+                        }
                     }
                 }
-            }
 
-            // Red assumed to go first.
-            if red + yellow == 6 * 7 {
-                None
+                row += d_r;
+                col += d_c;
             }
-            else if red == yellow {
-                Some(Player::Red)
+
+            None
+        }
+
+        fn win_chains() -> [(i32, i32, i32, i32); 25] {
+            [ (0, 0, 0, 1), (1, 0, 0, 1), (2, 0, 0, 1), (3, 0, 0, 1), (4, 0, 0, 1), (5, 0, 0, 1)  // rows
+            , (0, 0, 1, 0),  (0, 1, 1, 0),  (0, 2, 1, 0),  (0, 3, 1, 0),  (0, 4, 1, 0),  (0, 5, 1, 0), (0, 6, 1, 0)  // columns 
+            , (3, 0, -1, 1), (4, 0, -1, 1), (5, 0, -1, 1), (5, 1, -1, 1), (5, 2, -1, 1), (5, 3, -1, 1)  // downward right
+            , (3, 6, -1, -1), (4, 6, -1, -1), (5, 6, -1, -1), (5, 5, -1, -1), (5, 4, -1, -1), (5, 3, -1, -1)  // downward left
+            ]
+        }
+
+        // None if no winner (or game ongoing)
+        fn winner(&self) -> Option<Player> {
+            for (row, col, d_r, d_c) in Self::win_chains() {
+                if let Some(p) = self.win_on_chain(row, col, d_r, d_c) {
+                    return Some(p)
+                }
             }
-            else if red == yellow + 1 {
-                Some(Player::Yellow)
+
+            None
+        }
+
+        // May panic if the board is in a bad state. Returns None if the game is over.
+        fn next_to_move(&self) -> Option<Player> {
+            if let Some(_) = self.winner() {
+                None
             }
             else {
-                panic!("Board in illegal state");
+                let mut red = 0;
+                let mut yellow = 0;
+
+                for row in self.tiles {
+                    for tile in row {
+                        match tile {
+                            Tile::Piece(Player::Red) => red += 1,
+                            Tile::Piece(Player::Yellow) => yellow += 1,
+                            _ => (),
+                        }
+                    }
+                }
+
+                // Red assumed to go first.
+                if red + yellow == 6 * 7 {
+                    None
+                }
+                else if red == yellow {
+                    Some(Player::Red)
+                }
+                else if red == yellow + 1 {
+                    Some(Player::Yellow)
+                }
+                else {
+                    panic!("Board in illegal state");
+                }
             }
         }
-    }
 
-    pub fn next_boards(&self) -> Vec<Board> {
-        let mut boards = Vec::with_capacity(7);  // flamegraph says 9% was copying here.
-        let Some(player) = self.next_to_move()
-            else { return boards };
+        fn next_boards(&self) -> Vec<ArrayBoard> {
+            let mut boards = Vec::with_capacity(7);  // flamegraph says 9% was copying here.
+            let Some(player) = self.next_to_move()
+                else { return boards };
 
-        for i in 0..7 {
-            if let Ok(next) = self.play(i, player, false) {  // validation turned off for speed!
-                boards.push(next);
+            for i in 0..7 {
+                if let Ok(next) = self.play(i, player, false) {  // validation turned off for speed!
+                    boards.push(next);
+                }
             }
+
+            boards
         }
 
-        boards
-    }
+        fn prev_boards(&self) -> Vec<ArrayBoard> {
+            let mut boards = Vec::with_capacity(7);
 
-    pub fn prev_boards(&self) -> Vec<Board> {
-        let mut boards = Vec::with_capacity(7);
+            let Some(player) = self.next_to_move().map(|p| Player::opponent(&p))
+                else { return boards };
 
-        let Some(player) = self.next_to_move().map(|p| Player::opponent(&p))
-            else { return boards };
+            for i in 0..7 {
+                for j in 0..6 {
+                    if self.tiles[j][i] == Tile::Empty {
+                        if j == 0 {
+                            break;
+                        }
+
+                        if self.tiles[j - 1][i] == Tile::Piece(player) {
+                            let mut new_board = self.clone();
+
+                            new_board.tiles[j - 1][i] = Tile::Empty;
+                            boards.push(new_board);
+                        }
 
-        for i in 0..7 {
-            for j in 0..6 {
-                if self.tiles[j][i] == Tile::Empty {
-                    if j == 0 {
                         break;
                     }
-
-                    if self.tiles[j - 1][i] == Tile::Piece(player) {
+                    else if j == 5 && self.tiles[j][i] == Tile::Piece(player) {
                         let mut new_board = self.clone();
 
-                        new_board.tiles[j - 1][i] = Tile::Empty;
+                        new_board.tiles[j][i] = Tile::Empty;
                         boards.push(new_board);
                     }
+                }
+            }
+
+            boards
+        }
 
-                    break;
+        fn score_window(window: &[AnalyzedTile]) -> i32 {
+            assert!(window.len() == 4);
+
+            let mut yours = 0;
+            let mut enemies = 0;
+
+            for tile in window {
+                if tile == &AnalyzedTile::You {
+                    yours += 1;
                 }
-                else if j == 5 && self.tiles[j][i] == Tile::Piece(player) {
-                    let mut new_board = self.clone();
+                else if tile == &AnalyzedTile::Enemy {
+                    enemies += 1;
+                }
+            }
 
-                    new_board.tiles[j][i] = Tile::Empty;
-                    boards.push(new_board);
+            if enemies > 0 {
+                0
+            }
+            else {
+                match yours {
+                    1 => 5,
+                    2 => 50,
+                    3 => 500,
+                    _ => 0,
                 }
             }
         }
 
-        boards
-    }
+        // Score for the player along a given chain.
+        fn player_score_on_chain(&self, chain: (i32, i32, i32, i32), player: Player) -> i32 {
+            let (mut row, mut col, d_r, d_c) = chain;
+            let mut chain = vec![];
 
-    fn score_window(window: &[AnalyzedTile]) -> i32 {
-        assert!(window.len() == 4);
+            let mut score = 0;
 
-        let mut yours = 0;
-        let mut enemies = 0;
+            while Self::in_bounds(row, col) {
+                chain.push(match self.tiles[row as usize][col as usize] {
+                    Tile::Empty => AnalyzedTile::Empty,
+                    Tile::Piece(p) if p == player => AnalyzedTile::You,
+                    _ => AnalyzedTile::Enemy,
+                });
 
-        for tile in window {
-            if tile == &AnalyzedTile::You {
-                yours += 1;
+                row += d_r;
+                col += d_c;
             }
-            else if tile == &AnalyzedTile::Enemy {
-                enemies += 1;
-            }
-        }
 
-        if enemies > 0 {
-            0
+            // sliding window
+            for i in 0..chain.len() -3 {
+                score += Self::score_window(&chain[i..i+4]);
+            } 
+
+            score
         }
-        else {
-            match yours {
-                1 => 5,
-                2 => 50,
-                3 => 500,
-                _ => 0,
+
+        // Subjective score. Positive / High means win better for Red (player 1). 
+        // Negative / Low means better for Yellow.
+        fn get_score(&self) -> i32 {
+            match &self.winner() {
+                Some(Player::Red) => return 1000000000,
+                Some(Player::Yellow) => return -1000000000,
+                _ => (),
+            }
+
+            if self.next_to_move() == None {
+                return 0;
             }
+
+            let mut score = 0;
+            for chain in Self::win_chains() {
+                score += self.player_score_on_chain(chain, Player::Red);
+                score -= self.player_score_on_chain(chain, Player::Yellow);
+            }
+
+            score
         }
     }
 
-    // Score for the player along a given chain.
-    fn player_score_on_chain(&self, chain: (i32, i32, i32, i32), player: Player) -> i32 {
-        let (mut row, mut col, d_r, d_c) = chain;
-        let mut chain = vec![];
+    fn same_tiles(board: &Board, array: &ArrayBoard) -> bool {
+        (0..6).all(|row| (0..7).all(|col| board.tile(row, col) == array.tiles[row as usize][col as usize]))
+    }
 
-        let mut score = 0;
+    fn assert_equivalent(board: &Board, array: &ArrayBoard) {
+        assert!(same_tiles(board, array), "{}", board.display());
+        assert_eq!(board.winner(), array.winner());
+        assert_eq!(board.next_to_move(), array.next_to_move());
+        assert_eq!(board.get_score(), array.get_score());
+
+        let next = board.next_boards();
+        let array_next = array.next_boards();
+        assert_eq!(next.len(), array_next.len());
+        assert!(next.iter().zip(&array_next).all(|(b, a)| same_tiles(b, a)));
+
+        let prev = board.prev_boards();
+        let array_prev = array.prev_boards();
+        assert_eq!(prev.len(), array_prev.len());
+        assert!(prev.iter().zip(&array_prev).all(|(b, a)| same_tiles(b, a)));
+    }
 
-        while Self::in_bounds(row, col) {
-            chain.push(match self.tiles[row as usize][col as usize] {
-                Tile::Empty => AnalyzedTile::Empty,
-                Tile::Piece(p) if p == player => AnalyzedTile::You,
-                _ => AnalyzedTile::Enemy,
-            });
+    fn explore(board: &Board, array: &ArrayBoard, depth: i32) {
+        assert_equivalent(board, array);
 
-            row += d_r;
-            col += d_c;
+        if depth == 0 {
+            return;
         }
 
-        // sliding window
-        for i in 0..chain.len() -3 {
-            score += Self::score_window(&chain[i..i+4]);
-        } 
+        for (b, a) in board.next_boards().iter().zip(array.next_boards()) {
+            explore(b, &a, depth - 1);
+        }
+    }
 
-        score
+    #[test]
+    fn bitboard_matches_array_exhaustive() {
+        explore(&Board::new(), &ArrayBoard::new(), 4);
     }
 
-    #[allow(unused)]
-    fn pieces_played(&self) -> i32 {
-        let mut count = 0;
-        for row in self.tiles {
-            for tile in row {
-                if tile != Tile::Empty {
-                    count += 1;
+    #[test]
+    fn bitboard_matches_array_random_games() {
+        let mut seed: u64 = 12345;
+
+        for _ in 0..500 {
+            let mut board = Board::new();
+            let mut array = ArrayBoard::new();
+
+            while let Some(player) = board.next_to_move() {
+                assert_equivalent(&board, &array);
+
+                // LCG, good enough to wander around the game tree.
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let col = ((seed >> 33) % 7) as i32;
+
+                let played = board.play(col, player, true);
+                let array_played = array.play(col, player, false);
+                assert_eq!(played.is_ok(), array_played.is_ok());
+
+                if let (Ok(b), Ok(a)) = (played, array_played) {
+                    board = b;
+                    array = a;
                 }
             }
+
+            assert_equivalent(&board, &array);
         }
-        count
     }
 
-    // Subjective score. Positive / High means win better for Red (player 1). 
-    // Negative / Low means better for Yellow.
-    pub fn get_score(&self) -> i32 {
-        match &self.winner() {
-            Some(Player::Red) => return 1000000000,
-            Some(Player::Yellow) => return -1000000000,
-            _ => (),
-        }
+    #[test]
+    fn mirror_and_canonical() {
+        let board = Board::new()
+            .play(0, Player::Red, true).unwrap()
+            .play(2, Player::Yellow, true).unwrap()
+            .play(0, Player::Red, true).unwrap();
+
+        let mirrored = board.mirrored();
+        assert_eq!(mirrored.tile(0, 6), Tile::Piece(Player::Red));
+        assert_eq!(mirrored.tile(1, 6), Tile::Piece(Player::Red));
+        assert_eq!(mirrored.tile(0, 4), Tile::Piece(Player::Yellow));
+        assert_eq!(mirrored.mirrored(), board);
+
+        assert_eq!(board.canonical(), mirrored.canonical());
+        assert!(board.canonical() == board || board.canonical() == mirrored);
+        assert_eq!(mirrored.get_score(), board.get_score());
+
+        // Symmetric positions are their own mirror image.
+        let center = Board::new().play(3, Player::Red, true).unwrap();
+        assert_eq!(center.mirrored(), center);
+        assert_eq!(center.canonical(), center);
+    }
 
-        if self.next_to_move() == None {
-            return 0;
-        }
+    #[test]
+    fn move_strings() {
+        let board = Board::from_moves("4453").unwrap();
+        assert_eq!(board.tile(0, 3), Tile::Piece(Player::Red));
+        assert_eq!(board.tile(1, 3), Tile::Piece(Player::Yellow));
+        assert_eq!(board.tile(0, 4), Tile::Piece(Player::Red));
+        assert_eq!(board.tile(0, 2), Tile::Piece(Player::Yellow));
+
+        assert_eq!(Board::from_moves(" 44 53 "), Ok(board));
+        assert_eq!(moves_to_string(&parse_moves("4453214", 7).unwrap()), "4453214");
+
+        let err = Board::from_moves("44538").unwrap_err();
+        assert_eq!(err, SequenceError::NotAColumn { ply: 5, character: '8' });
+        assert_eq!(err.to_string(), "Move 5: '8' is not a column");
+
+        assert_eq!(Board::from_moves("1111111").unwrap_err(), SequenceError::IllegalMove { ply: 7, error: MoveError::ColumnFull(0) });
+        assert_eq!(Board::from_moves("12121212").unwrap_err().to_string(), "Move 8: Game is over");
+    }
 
-        let mut score = 0;
-        for chain in Self::win_chains() {
-            score += self.player_score_on_chain(chain, Player::Red);
-            score -= self.player_score_on_chain(chain, Player::Yellow);
+    #[test]
+    fn column_full() {
+        let mut board = Board::new();
+        for _ in 0..6 {
+            let player = board.next_to_move().unwrap();
+            board = board.play(3, player, true).expect("Works");
         }
 
-        score
+        assert_eq!(board.play(3, Player::Red, true), Err(MoveError::ColumnFull(3)));
+        assert_eq!(board.play(7, Player::Red, true), Err(MoveError::ColumnOutOfRange(7)));
+        assert_eq!(board.play(2, Player::Yellow, true), Err(MoveError::WrongPlayer(Player::Yellow)));
+
+        let won = Board::from_moves("1212121").unwrap();
+        assert_eq!(won.play(2, Player::Yellow, true), Err(MoveError::GameOver));
     }
-}
 
-#[cfg(test)]
-mod test {
-    pub use super::*;
+    #[test]
+    fn forced_moves() {
+        assert_eq!(Board::new().forced_moves(), None);
+
+        // Red can win, so Red should.
+        let board = Board::from_moves("121212").unwrap();
+        assert_eq!(board.winning_moves(Player::Red), vec![Move::Drop(0)]);
+        assert_eq!(board.forced_moves(), Some(vec![Move::Drop(0)]));
+
+        // Yellow has to block.
+        let board = Board::from_moves("12121").unwrap();
+        assert_eq!(board.winning_moves(Player::Yellow), vec![]);
+        assert_eq!(board.winning_moves(Player::Red), vec![Move::Drop(0)]);
+        assert_eq!(board.forced_moves(), Some(vec![Move::Drop(0)]));
+
+        // Two ways for Red to win, Yellow can only stop one.
+        let board = Board::from_moves("22334").unwrap();
+        assert_eq!(board.winning_moves(Player::Red), vec![Move::Drop(4), Move::Drop(0)]);
+        assert_eq!(board.forced_moves(), Some(vec![]));
+
+        assert_eq!(Board::from_moves("1212121").unwrap().winning_moves(Player::Yellow), vec![]);
+    }
 
     #[test]
-    fn prev_board_sane() {
-        let b1 = Board::new();
+    fn can_reach() {
+        let board = Board::from_moves("44").unwrap();
+        assert!(board.can_reach(&board));
+        assert!(board.can_reach(&Board::from_moves("4453").unwrap()));
+        assert!(!board.can_reach(&Board::from_moves("45").unwrap()));
+        assert!(!board.can_reach(&Board::new()));
+
+        // Pops can take pieces away again.
+        let popout = Board::empty(Dimensions::STANDARD, &PopOut);
+        assert!(!popout.can_reach(&popout.play(3, Player::Red, true).unwrap()));
+    }
 
-        let b2 = b1.play(0, Player::Red, true).expect("Works");
+    #[test]
+    fn keys_decode() {
+        for moves in ["", "4", "4453", "1212127777"] {
+            let board = Board::from_moves(moves).unwrap();
+            assert_eq!(Board::new().board_for_key(board.key()), Some(board), "{moves}");
+        }
 
-        assert!(b1 == b2.prev_boards()[0]);
+        // Keys with the solver's tag, or from a bigger board, aren't.
+        assert_eq!(Board::new().board_for_key(Board::new().key() | 1 << 63), None);
+        assert_eq!(Board::new().board_for_key(0b1111111), None);  // Seven high
+        let big = Board::empty(Dimensions::new(9, 12, 4).unwrap(), &Standard).play(0, Player::Red, true).unwrap();
+        assert_eq!(big.board_for_key(big.key()), None);
+    }
 
-        let b3 = b2.play(0, Player::Yellow, true).expect("Works");
+    #[test]
+    fn other_dimensions() {
+        assert!(Dimensions::new(10, 6, 4).is_err());
+        assert!(Dimensions::new(7, 6, 8).is_err());
+        assert!(Dimensions::new(7, 6, 1).is_err());
+
+        let dimensions = Dimensions::new(9, 7, 5).unwrap();
+        let mut board = Board::empty(dimensions, &Standard);
+        assert_ne!(board, Board::new());
+        assert_eq!(board.geometry().column_order(), &[4, 3, 5, 2, 6, 1, 7, 0, 8]);
+
+        // Four in a row isn't enough any more.
+        for col in [0, 0, 1, 1, 2, 2, 3, 3] {
+            board = board.play(col, board.next_to_move().unwrap(), true).unwrap();
+        }
+        assert_eq!(board.winner(), None);
+        assert_eq!(board.play(9, Player::Red, true), Err(MoveError::ColumnOutOfRange(9)));
+
+        let won = board.play(4, Player::Red, true).unwrap();
+        assert_eq!(won.winner(), Some(Player::Red));
+        assert_eq!(won.get_score(), 1000000000);
+
+        // Horizontal, vertical and both diagonals on the tall columns.
+        let geometry = board.geometry();
+        let line = |cells: &[(i32, i32)]| cells.iter().fold(0, |bits, (row, col)| bits | geometry.cell_mask(*row, *col));
+        assert!(geometry.has_line(line(&[(2, 8), (3, 8), (4, 8), (5, 8), (6, 8)])));
+        assert!(geometry.has_line(line(&[(2, 4), (3, 5), (4, 6), (5, 7), (6, 8)])));
+        assert!(geometry.has_line(line(&[(6, 0), (5, 1), (4, 2), (3, 3), (2, 4)])));
+        assert!(!geometry.has_line(line(&[(3, 8), (4, 8), (5, 8), (6, 8), (0, 0)])));
+
+        assert_eq!(board.mirrored().mirrored(), board);
+        assert_eq!(board.display().lines().last(), Some("  1  2  3  4  5  6  7  8  9  "));
+        assert_eq!(board.display().lines().count(), 10);
+    }
 
-        assert!(b2 == b3.prev_boards()[0]);
+    #[test]
+    fn small_board() {
+        let dimensions = Dimensions::new(4, 4, 3).unwrap();
+        let mut board = Board::empty(dimensions, &Standard);
 
-        let b4 = b3.play(0, Player::Red, true).expect("Works");
-        let b5 = b4.play(0, Player::Yellow, true).expect("Works");
-        let b6 = b5.play(0, Player::Red, true).expect("Works");
-        let b7 = b6.play(0, Player::Yellow, true).expect("Works");
+        for col in [0, 1, 0, 1] {
+            board = board.play(col, board.next_to_move().unwrap(), true).unwrap();
+        }
+        assert_eq!(board.next_boards().len(), 4);
 
-        assert!(b6 == b7.prev_boards()[0]);
+        let won = board.play(0, Player::Red, true).unwrap();
+        assert_eq!(won.winner(), Some(Player::Red));
+
+        // Full columns stop at the height.
+        let mut board = Board::empty(dimensions, &Standard);
+        for _ in 0..4 {
+            board = board.play(2, board.next_to_move().unwrap(), true).unwrap();
+        }
+        assert_eq!(board.play(2, Player::Red, true), Err(MoveError::ColumnFull(2)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn cylinder() {
+        // Columns 6, 7, 1 and 2 only make a four when the edges join up.
+        let moves = "647415";
+        let standard = Board::from_moves(moves).unwrap();
+        assert_eq!(standard.next_to_move(), Some(Player::Red));
+
+        let mut board = Board::empty(Dimensions::STANDARD, &Cylinder);
+        for m in parse_moves(moves, 7).unwrap() {
+            board = board.play_move(m, board.next_to_move().unwrap(), true).unwrap();
+        }
+        assert_eq!(board.winner(), None);
+
+        let won = board.play(1, Player::Red, true).unwrap();
+        assert_eq!(won.winner(), Some(Player::Red), "{}", won.display());
+        assert_eq!(standard.play(1, Player::Red, true).unwrap().winner(), None);
+
+        // Diagonals wrap too: (0, 5), (1, 6), (2, 0), (3, 1).
+        let geometry = board.geometry();
+        let line = [(0, 5), (1, 6), (2, 0), (3, 1)].iter().fold(0, |bits, (row, col)| bits | geometry.cell_mask(*row, *col));
+        assert!(geometry.has_line(line));
+        assert!(!Board::new().geometry().has_line(line));
+
+        // Every row gets 7 horizontal windows instead of 4, and each diagonal direction 21
+        // instead of 12.
+        assert_eq!(Board::new().geometry().windows.len(), 69);
+        assert_eq!(geometry.windows.len(), 21 + 42 + 21 + 21);
+
+        // A line as long as the row has only one window per row.
+        let narrow = Board::empty(Dimensions::new(4, 4, 4).unwrap(), &Cylinder);
+        assert_eq!(narrow.geometry().windows.len(), 4 + 4 + 4 + 4);
+    }
+}
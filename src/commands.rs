@@ -0,0 +1,151 @@
+use crate::{board::{Move, MoveError, Player}, engine::{Controller, Difficulty, parse_level, parse_depth, parse_blunder_rate}, eval, mcts::parse_playout, weights::Weights};
+
+// What can be typed at the prompt.
+
+pub const HELP: &str = "\
+Commands:
+  <column>                      Play in that column, counting from 1
+  p<column>                     Pop your piece off the bottom (PopOut only)
+  u, undo                       Take back a move
+  r, redo                       Play an undone move again
+  jump <ply>                    Go to the position after that many moves
+  load <moves>                  Start from a move string, like 4453214
+  moves                         Print the move string for this position
+  set <red|yellow> <player> [depth <n>] [blunder <rate>] [eval <name>]
+      [weights <file>] [playouts <kind>]
+                                Change who plays a side. Players are human,
+                                random, greedy, shallow, perfect, or mcts.
+                                Evaluators are default, threats, parity, or
+                                center. Playouts are heuristic or random
+  seed <n>                      Reseed the engines
+  help                          Show this message";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Play (Move),
+    Undo,
+    Redo,
+    Jump (usize),
+    Load (String),
+    Moves,
+    Set (Player, Controller),
+    Seed (u64),
+    Help,
+}
+
+fn parse_player(name: &str) -> Result<Player, String> {
+    match name {
+        "red" | "r" => Ok(Player::Red),
+        "yellow" | "y" => Ok(Player::Yellow),
+        _ => Err(format!("Unknown side: {name}")),
+    }
+}
+
+fn parse_set(words: &[&str]) -> Result<Command, String> {
+    let [side, kind, rest @ ..] = words
+        else { return Err("Usage: set <red|yellow> <player> [depth <n>] [blunder <rate>] [eval <name>] [weights <file>] [playouts <kind>]".into()) };
+
+    let player = parse_player(side)?;
+
+    if *kind == "human" {
+        return match rest {
+            [] => Ok(Command::Set(player, Controller::Human)),
+            _ => Err("Humans don't take settings".into()),
+        };
+    }
+
+    let mut difficulty = Difficulty { level: parse_level(kind)?, ..Difficulty::default() };
+    let mut weights = Weights::DEFAULT;
+
+    for setting in rest.chunks(2) {
+        match setting {
+            ["depth", depth] => difficulty.depth = parse_depth(depth)?,
+            ["blunder", rate] => difficulty.blunder_rate = parse_blunder_rate(rate)?,
+            ["eval", name] => difficulty.evaluator = eval::by_name(name)?,
+            ["weights", path] => weights = Weights::load(path)?,
+            ["playouts", kind] => difficulty.playouts = parse_playout(kind)?,
+            _ => return Err(format!("Unknown setting: {}", setting.join(" "))),
+        }
+    }
+
+    difficulty.evaluator = eval::with_weights(difficulty.evaluator, weights);
+
+    Ok(Command::Set(player, Controller::Engine(difficulty)))
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    match words.as_slice() {
+        [] => Err("Bad input, try again".into()),
+        ["help"] => Ok(Command::Help),
+        ["u" | "undo"] => Ok(Command::Undo),
+        ["r" | "redo"] => Ok(Command::Redo),
+        ["jump" | "j", ply] => ply.parse()
+            .map(Command::Jump)
+            .map_err(|_| format!("Bad ply: {ply}")),
+        ["load", moves @ ..] if !moves.is_empty() => Ok(Command::Load(moves.concat())),
+        ["moves"] => Ok(Command::Moves),
+        ["set", rest @ ..] => parse_set(rest),
+        ["seed", seed] => seed.parse()
+            .map(Command::Seed)
+            .map_err(|_| format!("Bad seed: {seed}")),
+        [word] => {
+            let (pop, col) = match word.strip_prefix('p') {
+                Some(col) => (true, col),
+                None => (false, *word),
+            };
+
+            match col.parse::<i32>() {
+                // Subtract 1 to get to board coordinates. The board knows how wide it is.
+                Ok(col) if col >= 1 && pop => Ok(Command::Play(Move::Pop(col - 1))),
+                Ok(col) if col >= 1 => Ok(Command::Play(Move::Drop(col - 1))),
+                Ok(col) => Err(MoveError::ColumnOutOfRange(col - 1).to_string()),
+                Err(_) => Err("Bad input, try again".into()),
+            }
+        }
+        _ => Err("Bad input, try again".into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{engine::Level, mcts::Playout};
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse(" 4\n"), Ok(Command::Play(Move::Drop(3))));
+        assert_eq!(parse("8"), Ok(Command::Play(Move::Drop(7))));
+        assert!(parse("0").is_err());
+        assert_eq!(parse("p3"), Ok(Command::Play(Move::Pop(2))));
+        assert!(parse("p").is_err());
+        assert!(parse("pp3").is_err());
+        assert!(parse("").is_err());
+        assert_eq!(parse("help"), Ok(Command::Help));
+        assert_eq!(parse("seed 12"), Ok(Command::Seed(12)));
+        assert_eq!(parse("u"), Ok(Command::Undo));
+        assert_eq!(parse("redo"), Ok(Command::Redo));
+        assert_eq!(parse("jump 3"), Ok(Command::Jump(3)));
+        assert!(parse("jump -1").is_err());
+        assert_eq!(parse("load 4453 214"), Ok(Command::Load("4453214".into())));
+        assert!(parse("load").is_err());
+        assert_eq!(parse("moves"), Ok(Command::Moves));
+
+        assert_eq!(parse("set red human"), Ok(Command::Set(Player::Red, Controller::Human)));
+        assert_eq!(parse("set yellow shallow depth 6 blunder 0.5"), Ok(Command::Set(Player::Yellow,
+            Controller::Engine(Difficulty { level: Level::Shallow, depth: 6, blunder_rate: 0.5, ..Difficulty::default() }))));
+        assert_eq!(parse("set red greedy eval parity"), Ok(Command::Set(Player::Red,
+            Controller::Engine(Difficulty { level: Level::Greedy, evaluator: eval::ALL[2], ..Difficulty::default() }))));
+        assert!(parse("set red greedy eval magic").is_err());
+        assert!(matches!(parse("set yellow mcts playouts random"), Ok(Command::Set(Player::Yellow,
+            Controller::Engine(Difficulty { level: Level::Mcts, playouts: Playout::Random, .. })))));
+        assert!(parse("set yellow mcts playouts clever").is_err());
+        assert!(parse("set red greedy weights weights/default.toml").is_ok());
+        assert!(parse("set red greedy weights nowhere.toml").is_err());
+
+        assert!(parse("set yellow shallow depth").is_err());
+        assert!(parse("set blue greedy").is_err());
+        assert!(parse("set red human depth 3").is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use std::{fmt, time::Duration};
+
+use crate::{analysis::{Analysis, Limits, HEURISTIC_DEPTH}, board::{Board, Move, Player}, eval::{self, Evaluator}, mcts::Playout, random::Rng};
+
+// Computer opponents, from hopeless to as good as the analysis gets.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Random,  // Any legal move
+    Greedy,  // Best score one move ahead
+    Shallow,  // Heuristic search to a fixed depth
+    Perfect,  // Everything the analysis thread has, solver included
+    Mcts,  // Monte Carlo tree search in the analysis thread instead
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Level::Random => "random",
+            Level::Greedy => "greedy",
+            Level::Shallow => "shallow",
+            Level::Perfect => "perfect",
+            Level::Mcts => "mcts",
+        })
+    }
+}
+
+pub fn parse_level(name: &str) -> Result<Level, String> {
+    match name {
+        "random" => Ok(Level::Random),
+        "greedy" => Ok(Level::Greedy),
+        "shallow" => Ok(Level::Shallow),
+        "perfect" | "engine" => Ok(Level::Perfect),
+        "mcts" => Ok(Level::Mcts),
+        _ => Err(format!("Unknown level: {name}")),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Difficulty {
+    pub level: Level,
+    pub depth: i32,  // Only used by Shallow
+    pub blunder_rate: f64,  // Chance of throwing away the move for a random one
+    pub evaluator: &'static dyn Evaluator,  // What the searching levels go by
+    pub playouts: Playout,  // Only used by Mcts
+}
+
+impl Default for Difficulty {
+    fn default() -> Difficulty {
+        Difficulty {
+            level: Level::Perfect,
+            depth: 4,
+            blunder_rate: 0.0,
+            evaluator: eval::DEFAULT,
+            playouts: Playout::Heuristic,
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.level)?;
+
+        if self.level == Level::Shallow {
+            write!(f, ", depth {}", self.depth)?;
+        }
+        if self.level == Level::Mcts && self.playouts != Playout::Heuristic {
+            write!(f, ", {} playouts", self.playouts)?;
+        }
+        if self.blunder_rate > 0.0 {
+            write!(f, ", blunders {}%", self.blunder_rate * 100.0)?;
+        }
+        if !matches!(self.level, Level::Random | Level::Mcts) && *self.evaluator != *eval::DEFAULT {
+            write!(f, ", {} eval", self.evaluator)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Controller {
+    Human,
+    Engine (Difficulty),
+}
+
+pub fn parse_controller(name: &str) -> Result<Controller, String> {
+    match name {
+        "human" => Ok(Controller::Human),
+        _ => parse_level(name)
+            .map(|level| Controller::Engine(Difficulty { level, ..Difficulty::default() }))
+            .map_err(|_| format!("Unknown player kind: {name}")),
+    }
+}
+
+pub fn parse_blunder_rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(r) if (0.0..=1.0).contains(&r) => Ok(r),
+        _ => Err(format!("Bad blunder rate: {rate} (should be between 0 and 1)")),
+    }
+}
+
+pub fn parse_depth(depth: &str) -> Result<i32, String> {
+    match depth.parse::<i32>() {
+        Ok(d) if (1..=HEURISTIC_DEPTH).contains(&d) => Ok(d),
+        _ => Err(format!("Bad depth: {depth} (should be 1 to {HEURISTIC_DEPTH})")),
+    }
+}
+
+pub struct Engine {
+    rng: Rng,
+}
+
+impl Engine {
+    pub fn new(seed: u64) -> Engine {
+        Engine { rng: Rng::new(seed) }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    fn random_move(&mut self, board: &Board) -> Move {
+        let legal = board.legal_moves();
+        legal[self.rng.below(legal.len())]
+    }
+
+    // Ties are broken at random, otherwise greedy would always open in the same column.
+    fn greedy_move(&mut self, board: &Board, player: Player, evaluator: &dyn Evaluator) -> Move {
+        let mut best = vec![];
+        let mut best_score = i32::MIN;
+
+        for m in board.moves() {
+            let Ok(child) = board.play_move(m, player, false)
+                else { continue };
+
+            let score = match player {
+                Player::Red => evaluator.score(&child),
+                Player::Yellow => -evaluator.score(&child),
+            };
+
+            if score > best_score {
+                best_score = score;
+                best.clear();
+            }
+            if score == best_score {
+                best.push(m);
+            }
+        }
+
+        best[self.rng.below(best.len())]
+    }
+
+    // The analysis thread should already have `board` as its root, searching it the way
+    // this difficulty does.
+    pub fn choose(&mut self, board: &Board, difficulty: &Difficulty, analysis: &Analysis, time: Duration) -> Move {
+        let player = board.next_to_move().expect("game is not over");
+
+        if self.rng.chance(difficulty.blunder_rate) {
+            return self.random_move(board);
+        }
+
+        let limits = match difficulty.level {
+            Level::Random => return self.random_move(board),
+            Level::Greedy => return self.greedy_move(board, player, difficulty.evaluator),
+            Level::Shallow => Limits { time, depth: Some(difficulty.depth) },
+            Level::Perfect | Level::Mcts => Limits { time, depth: None },
+        };
+
+        // Only comes back empty if the analysis thinks the game is over, which it shouldn't.
+        analysis.best_move(limits).unwrap_or_else(|| self.random_move(board))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_controllers() {
+        assert_eq!(parse_controller("human"), Ok(Controller::Human));
+        assert_eq!(parse_controller("engine"), Ok(Controller::Engine(Difficulty::default())));
+        assert!(matches!(parse_controller("greedy"), Ok(Controller::Engine(Difficulty { level: Level::Greedy, .. }))));
+        assert!(matches!(parse_controller("mcts"), Ok(Controller::Engine(Difficulty { level: Level::Mcts, playouts: Playout::Heuristic, .. }))));
+        assert!(parse_controller("robot").is_err());
+
+        assert_eq!(parse_blunder_rate("0.25"), Ok(0.25));
+        assert!(parse_blunder_rate("2").is_err());
+        assert!(parse_depth("0").is_err());
+        assert!(parse_depth("11").is_err());
+    }
+
+    #[test]
+    fn greedy_takes_the_win() {
+        let mut board = Board::new();
+        for col in [0, 1, 0, 1, 0, 1] {
+            board = board.play(col, board.next_to_move().unwrap(), true).unwrap();
+        }
+
+        let mut engine = Engine::new(1);
+        for evaluator in eval::ALL {
+            assert_eq!(engine.greedy_move(&board, Player::Red, evaluator), Move::Drop(0));
+        }
+    }
+
+    #[test]
+    fn seeded_games_repeat() {
+        let play_out = |seed| {
+            let mut engine = Engine::new(seed);
+            let mut board = Board::new();
+            let mut moves = vec![];
+
+            while let Some(player) = board.next_to_move() {
+                let m = if player == Player::Red {
+                    engine.random_move(&board)
+                } else {
+                    engine.greedy_move(&board, player, eval::DEFAULT)
+                };
+                board = board.play_move(m, player, true).unwrap();
+                moves.push(m);
+            }
+
+            moves
+        };
+
+        assert_eq!(play_out(99), play_out(99));
+        assert_ne!(play_out(99), play_out(100));
+    }
+}
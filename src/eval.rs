@@ -0,0 +1,156 @@
+use std::{fmt, sync::Mutex};
+
+use crate::{board::{Board, Player}, weights::Weights};
+
+pub mod windows;
+pub mod threats;
+pub mod parity;
+pub mod center;
+
+pub use windows::Windows;
+pub use threats::Threats;
+pub use parity::Parity;
+pub use center::Center;
+
+// Guesses how good a position is, for the searches to go by once they run out of depth.
+// Engines each get one, so they can be played against each other.
+pub trait Evaluator: Sync {
+    fn name(&self) -> &'static str;
+
+    fn weights(&self) -> &Weights;
+
+    // The weights this one actually reads, which are the only ones worth tuning.
+    fn tunable(&self) -> &'static [&'static str];
+
+    // The same evaluator going by other weights.
+    fn with_weights(&self, weights: Weights) -> Box<dyn Evaluator>;
+
+    // Positive / High means better for Red (player 1), negative / low better for Yellow.
+    // Only asked about games still going.
+    fn heuristic(&self, board: &Board) -> i32;
+
+    // Subjective score, with finished games scored for what they are. Anything within 100
+    // or so of the win weight is a forced win being counted down by ply, see analysis, so
+    // heuristics get held short of that however much they add up to.
+    fn score(&self, board: &Board) -> i32 {
+        match board.winner() {
+            Some(Player::Red) => return self.weights().win,
+            Some(Player::Yellow) => return -self.weights().win,
+            None => (),
+        }
+
+        if board.next_to_move().is_none() {
+            return 0;
+        }
+
+        let limit = (self.weights().win - 101).max(0);
+        self.heuristic(board).clamp(-limit, limit)
+    }
+}
+
+impl PartialEq for dyn Evaluator {
+    fn eq(&self, other: &dyn Evaluator) -> bool {
+        self.name() == other.name() && self.weights() == other.weights()
+    }
+}
+
+impl fmt::Debug for dyn Evaluator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for dyn Evaluator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+pub const DEFAULT: &dyn Evaluator = &Windows { weights: Weights::DEFAULT };
+
+// Every evaluator there is, with the built in weights. New ones go here to be selectable.
+pub const ALL: [&dyn Evaluator; 4] = [
+    DEFAULT,
+    &Threats { weights: Weights::DEFAULT },
+    &Parity { weights: Weights::DEFAULT },
+    &Center { weights: Weights::DEFAULT },
+];
+
+pub fn by_name(name: &str) -> Result<&'static dyn Evaluator, String> {
+    let name = match name {
+        "windows" => "default",
+        "threat" => "threats",
+        _ => name,
+    };
+
+    ALL.into_iter()
+        .find(|evaluator| evaluator.name() == name)
+        .ok_or(format!("Unknown evaluator: {name}"))
+}
+
+// `evaluator` going by `weights`. Each one is built the first time it's asked for and kept
+// for good, so engines can keep holding on to plain references.
+pub fn with_weights(evaluator: &dyn Evaluator, weights: Weights) -> &'static dyn Evaluator {
+    static REWEIGHTED: Mutex<Vec<&'static dyn Evaluator>> = Mutex::new(Vec::new());
+
+    let mut reweighted = REWEIGHTED.lock().unwrap();
+    let wanted = |e: &&dyn Evaluator| e.name() == evaluator.name() && *e.weights() == weights;
+
+    if let Some(found) = ALL.into_iter().chain(reweighted.iter().copied()).find(wanted) {
+        return found;
+    }
+
+    let built: &'static dyn Evaluator = Box::leak(evaluator.with_weights(weights));
+    reweighted.push(built);
+    built
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{board::Dimensions, rules::Standard};
+
+    #[test]
+    fn finds_evaluators() {
+        for evaluator in ALL {
+            assert_eq!(by_name(evaluator.name()), Ok(evaluator));
+        }
+        assert_eq!(by_name("threat"), Ok(ALL[1]));
+        assert!(by_name("magic").is_err());
+
+        let weights = Weights { three: 900, ..Weights::DEFAULT };
+        let sharper = with_weights(DEFAULT, weights);
+        assert_eq!(sharper.weights(), &weights);
+        assert_ne!(sharper, DEFAULT);
+        assert!(std::ptr::addr_eq(sharper, with_weights(DEFAULT, weights)));
+        assert_eq!(with_weights(sharper, Weights::DEFAULT), DEFAULT);
+    }
+
+    #[test]
+    fn wins_are_wins() {
+        let won = Board::from_moves("1212121").unwrap();
+
+        // Full, and nobody has three in a row.
+        let mut drawn = Board::empty(Dimensions::new(3, 2, 3).unwrap(), &Standard);
+        for col in [0, 1, 2, 0, 1, 2] {
+            drawn = drawn.play(col, drawn.next_to_move().unwrap(), true).unwrap();
+        }
+        assert_eq!(drawn.next_to_move(), None);
+
+        for evaluator in ALL {
+            assert_eq!(evaluator.score(&won), Weights::DEFAULT.win);
+            assert_eq!(evaluator.score(&drawn), 0);
+            assert_eq!(evaluator.score(&Board::new()), 0, "{evaluator}");
+        }
+    }
+
+    #[test]
+    fn heuristics_stay_short_of_a_win() {
+        // Far more than a small win weight, with no win on the board.
+        let board = Board::from_moves("41517").unwrap();
+        let small = DEFAULT.with_weights(Weights { win: 300, ..Weights::DEFAULT });
+        assert!(small.heuristic(&board) > 300);
+        assert_eq!(small.score(&board), 199);
+        assert_eq!(small.score(&Board::from_moves("1212121").unwrap()), 300);
+    }
+}
@@ -0,0 +1,41 @@
+use crate::{board::{Board, Player}, weights::Weights};
+
+use super::Evaluator;
+
+// Pieces are worth more the closer they are to the middle, where they take part in the
+// most lines. Knows nothing about lines themselves.
+pub struct Center {
+    pub weights: Weights,
+}
+
+impl Evaluator for Center {
+    fn name(&self) -> &'static str {
+        "center"
+    }
+
+    fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    fn tunable(&self) -> &'static [&'static str] {
+        &["center"]
+    }
+
+    fn with_weights(&self, weights: Weights) -> Box<dyn Evaluator> {
+        Box::new(Center { weights })
+    }
+
+    fn heuristic(&self, board: &Board) -> i32 {
+        let geometry = board.geometry();
+        let width = geometry.width();
+
+        (0..width).map(|col| {
+            let steps = width - (2 * col - (width - 1)).abs();
+            let column = geometry.column_mask(col);
+            let red = (board.pieces(Player::Red) & column).count_ones() as i32;
+            let yellow = (board.pieces(Player::Yellow) & column).count_ones() as i32;
+
+            self.weights.center * steps * (red - yellow)
+        }).sum()
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{board::{Bits, Board, Player}, threats::{odd_rows, threat_cells, zugzwang}, weights::Weights};
+
+use super::{Evaluator, Windows};
+
+// The default, plus threats weighted by the row they're on (see threats), plus a bonus for
+// whoever the threats say wins by zugzwang.
+pub struct Parity {
+    pub weights: Weights,
+}
+
+impl Parity {
+    fn side(&self, board: &Board, player: Player, odd_rows: Bits) -> i32 {
+        let threats = threat_cells(board, player);
+        let good = match player {
+            Player::Red => threats & odd_rows,
+            Player::Yellow => threats & !odd_rows,
+        };
+
+        self.weights.parity_good * good.count_ones() as i32 + self.weights.parity_bad * (threats & !good).count_ones() as i32
+    }
+}
+
+impl Evaluator for Parity {
+    fn name(&self) -> &'static str {
+        "parity"
+    }
+
+    fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    fn tunable(&self) -> &'static [&'static str] {
+        &["one", "two", "three", "parity_good", "parity_bad", "zugzwang"]
+    }
+
+    fn with_weights(&self, weights: Weights) -> Box<dyn Evaluator> {
+        Box::new(Parity { weights })
+    }
+
+    fn heuristic(&self, board: &Board) -> i32 {
+        let odd_rows = odd_rows(board);
+        let windows = Windows { weights: self.weights };
+
+        let zugzwang = match zugzwang(board) {
+            Some(Player::Red) => self.weights.zugzwang,
+            Some(Player::Yellow) => -self.weights.zugzwang,
+            None => 0,
+        };
+
+        windows.heuristic(board) + self.side(board, Player::Red, odd_rows) - self.side(board, Player::Yellow, odd_rows) + zugzwang
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefers_own_parity() {
+        // A red three on the bottom row, wanting a cell on the first row. With nothing
+        // from Yellow, that's a zugzwang win too. Then one on the second row, which is
+        // Yellow's, and doesn't count for zugzwang.
+        let own = Board::from_moves("11273").unwrap();
+        let other = Board::from_moves("3112273").unwrap();
+
+        let parity = Parity { weights: Weights::DEFAULT };
+        let windows = Windows { weights: Weights::DEFAULT };
+        let bonus = |board: &Board| parity.heuristic(board) - windows.heuristic(board);
+        assert_eq!(bonus(&own), 300 + 2000);
+        assert_eq!(bonus(&other), 50);
+    }
+}
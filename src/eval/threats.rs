@@ -0,0 +1,40 @@
+use crate::{board::{Board, Player}, threats::threat_cells, weights::Weights};
+
+use super::Evaluator;
+
+// Only cares about cells that would finish a line. Ones that can be played into right
+// away count for more, since the other side has to deal with them now.
+pub struct Threats {
+    pub weights: Weights,
+}
+
+impl Threats {
+    fn side(&self, board: &Board, player: Player) -> i32 {
+        let threats = threat_cells(board, player);
+        let playable = threats & board.playable();
+
+        self.weights.threat * threats.count_ones() as i32 + self.weights.playable_threat * playable.count_ones() as i32
+    }
+}
+
+impl Evaluator for Threats {
+    fn name(&self) -> &'static str {
+        "threats"
+    }
+
+    fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    fn tunable(&self) -> &'static [&'static str] {
+        &["threat", "playable_threat"]
+    }
+
+    fn with_weights(&self, weights: Weights) -> Box<dyn Evaluator> {
+        Box::new(Threats { weights })
+    }
+
+    fn heuristic(&self, board: &Board) -> i32 {
+        self.side(board, Player::Red) - self.side(board, Player::Yellow)
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{board::{Board, Player}, weights::Weights};
+
+use super::Evaluator;
+
+// Counts every line that could still be made, by how close each one is to done.
+pub struct Windows {
+    pub weights: Weights,
+}
+
+// Scored by how close the window is to being a line. Long lines only start to count
+// once they're three pieces short.
+pub fn score_window(weights: &Weights, yours: u32, enemies: u32, connect: u32) -> i32 {
+    if enemies > 0 || yours == 0 {
+        0
+    }
+    else {
+        match connect - yours {
+            1 => weights.three,
+            2 => weights.two,
+            3 => weights.one,
+            _ => 0,
+        }
+    }
+}
+
+impl Evaluator for Windows {
+    fn name(&self) -> &'static str {
+        "default"
+    }
+
+    fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    fn tunable(&self) -> &'static [&'static str] {
+        &["one", "two", "three"]
+    }
+
+    fn with_weights(&self, weights: Weights) -> Box<dyn Evaluator> {
+        Box::new(Windows { weights })
+    }
+
+    fn heuristic(&self, board: &Board) -> i32 {
+        let connect = board.dimensions().connect as u32;
+        let red_pieces = board.pieces(Player::Red);
+        let yellow_pieces = board.pieces(Player::Yellow);
+
+        let mut score = 0;
+        for window in board.geometry().windows() {
+            let red = (red_pieces & window).count_ones();
+            let yellow = (yellow_pieces & window).count_ones();
+
+            score += score_window(&self.weights, red, yellow, connect);
+            score -= score_window(&self.weights, yellow, red, connect);
+        }
+
+        score
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{board::{Board, Dimensions, Move, Player, MoveError, SequenceError, parse_moves, moves_to_string}, engine::Controller};
+
+// A game in progress: who is playing, and every move so far. Undone moves are kept
+// around for redo until a different move is played over them.
+
+// PopOut games can go round in circles. The third time a position comes up, it's a draw.
+const REPETITIONS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    InProgress,
+    Win (Player),
+    Draw,
+}
+
+#[derive(Clone, Debug)]
+pub struct Game {
+    moves: Vec<Move>,
+    boards: Vec<Board>,  // boards[i] is the position after i moves
+    ply: usize,  // How many of the moves are in effect
+    red: Controller,
+    yellow: Controller,
+}
+
+impl Game {
+    // Starts from `start`, which is normally empty.
+    pub fn new(start: Board, red: Controller, yellow: Controller) -> Game {
+        Game {
+            moves: vec![],
+            boards: vec![start],
+            ply: 0,
+            red,
+            yellow,
+        }
+    }
+
+    // Game with the move string already played. See board::parse_moves.
+    pub fn from_moves(start: Board, moves: &str, red: Controller, yellow: Controller) -> Result<Game, SequenceError> {
+        let width = start.dimensions().width;
+        let mut game = Game::new(start, red, yellow);
+
+        for (i, m) in parse_moves(moves, width)?.into_iter().enumerate() {
+            game.play(m).map_err(|error| SequenceError::IllegalMove { ply: i + 1, error })?;
+        }
+
+        Ok(game)
+    }
+
+    // Move string for the current position.
+    pub fn to_moves(&self) -> String {
+        moves_to_string(self.moves())
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        self.boards[0].dimensions()
+    }
+
+    // Where the game started from.
+    pub fn start(&self) -> &Board {
+        &self.boards[0]
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.boards[self.ply]
+    }
+
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    // Moves leading to the current position.
+    pub fn moves(&self) -> &[Move] {
+        &self.moves[..self.ply]
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        let board = self.board();
+
+        match (board.winner(), board.next_to_move()) {
+            (Some(player), _) => Outcome::Win(player),
+            (None, None) => Outcome::Draw,
+            (None, Some(_)) if self.repetitions() >= REPETITIONS => Outcome::Draw,
+            (None, Some(_)) => Outcome::InProgress,
+        }
+    }
+
+    // How many times the current position has come up so far, this time included.
+    fn repetitions(&self) -> usize {
+        let board = self.board();
+        self.boards[..=self.ply].iter().filter(|b| *b == board).count()
+    }
+
+    // None once the game is over, repetitions included.
+    pub fn to_move(&self) -> Option<Player> {
+        match self.outcome() {
+            Outcome::InProgress => self.board().next_to_move(),
+            _ => None,
+        }
+    }
+
+    pub fn controller(&self, player: Player) -> Controller {
+        match player {
+            Player::Red => self.red,
+            Player::Yellow => self.yellow,
+        }
+    }
+
+    pub fn set_controller(&mut self, player: Player, controller: Controller) {
+        match player {
+            Player::Red => self.red = controller,
+            Player::Yellow => self.yellow = controller,
+        }
+    }
+
+    // Plays for whoever is to move. Anything that could have been redone is dropped.
+    pub fn play(&mut self, m: Move) -> Result<(), MoveError> {
+        let Some(player) = self.to_move()
+            else { return Err(MoveError::GameOver) };
+
+        let next = self.board().play_move(m, player, true)?;
+
+        self.moves.truncate(self.ply);
+        self.boards.truncate(self.ply + 1);
+
+        self.moves.push(m);
+        self.boards.push(next);
+        self.ply += 1;
+
+        Ok(())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.ply > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.ply < self.moves.len()
+    }
+
+    // False if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        if !self.can_undo() {
+            return false;
+        }
+
+        self.ply -= 1;
+        true
+    }
+
+    // False if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        if !self.can_redo() {
+            return false;
+        }
+
+        self.ply += 1;
+        true
+    }
+
+    // Goes to any point in the history, undone moves included.
+    pub fn jump_to(&mut self, ply: usize) -> Result<(), String> {
+        if ply > self.moves.len() {
+            return Err(format!("Only {} moves have been played", self.moves.len()));
+        }
+
+        self.ply = ply;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rules::{PopOut, Standard};
+
+    fn new_game() -> Game {
+        Game::new(Board::new(), Controller::Human, Controller::Human)
+    }
+
+    fn drops(cols: &[i32]) -> Vec<Move> {
+        cols.iter().map(|col| Move::Drop(*col)).collect()
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut game = new_game();
+        game.play(Move::Drop(3)).unwrap();
+        game.play(Move::Drop(4)).unwrap();
+        let after_two = game.board().clone();
+
+        assert!(game.undo());
+        assert_eq!(game.ply(), 1);
+        assert_eq!(game.moves(), drops(&[3]));
+        assert_eq!(game.board().next_to_move(), Some(Player::Yellow));
+
+        assert!(game.undo());
+        assert!(!game.undo());
+        assert_eq!(game.board(), &Board::new());
+
+        assert!(game.redo());
+        assert!(game.redo());
+        assert!(!game.redo());
+        assert_eq!(game.board(), &after_two);
+    }
+
+    #[test]
+    fn playing_drops_redo() {
+        let mut game = new_game();
+        game.play(Move::Drop(3)).unwrap();
+        game.play(Move::Drop(4)).unwrap();
+
+        game.undo();
+        game.play(Move::Drop(2)).unwrap();
+
+        assert!(!game.can_redo());
+        assert_eq!(game.moves(), drops(&[3, 2]));
+    }
+
+    #[test]
+    fn jumps_and_outcome() {
+        let mut game = new_game();
+        for m in drops(&[0, 1, 0, 1, 0, 1, 0]) {
+            game.play(m).unwrap();
+        }
+
+        assert_eq!(game.outcome(), Outcome::Win(Player::Red));
+        assert_eq!(game.play(Move::Drop(2)), Err(MoveError::GameOver));
+
+        game.jump_to(2).unwrap();
+        assert_eq!(game.outcome(), Outcome::InProgress);
+        assert_eq!(game.moves(), drops(&[0, 1]));
+
+        game.jump_to(7).unwrap();
+        assert_eq!(game.outcome(), Outcome::Win(Player::Red));
+        assert!(game.jump_to(8).is_err());
+    }
+
+    #[test]
+    fn move_strings() {
+        let mut game = Game::from_moves(Board::new(), "4453214", Controller::Human, Controller::Human).unwrap();
+        assert_eq!(game.to_moves(), "4453214");
+        assert_eq!(game.board(), &Board::from_moves("4453214").unwrap());
+
+        game.undo();
+        assert_eq!(game.to_moves(), "445321");
+
+        let err = Game::from_moves(Board::new(), "44x", Controller::Human, Controller::Human).unwrap_err();
+        assert_eq!(err.ply(), 3);
+
+        // Columns past the edge of a narrow board aren't columns at all.
+        let narrow = Board::empty(Dimensions::new(5, 4, 3).unwrap(), &Standard);
+        assert_eq!(Game::from_moves(narrow.clone(), "336", Controller::Human, Controller::Human).unwrap_err(),
+            SequenceError::NotAColumn { ply: 3, character: '6' });
+
+        let game = Game::from_moves(narrow.clone(), "3344", Controller::Human, Controller::Human).unwrap();
+        assert_eq!(game.dimensions(), narrow.dimensions());
+        assert_eq!(game.to_moves(), "3344");
+    }
+
+    #[test]
+    fn popout_repetition() {
+        let start = Board::empty(Dimensions::STANDARD, &PopOut);
+        let mut game = Game::from_moves(start.clone(), "12p1p2 12p1", Controller::Human, Controller::Human).unwrap();
+        assert_eq!(game.outcome(), Outcome::InProgress);
+        assert_eq!(game.to_moves(), "12p1p212p1");
+
+        // Third time back at the empty board.
+        game.play(Move::Pop(1)).unwrap();
+        assert_eq!(game.board(), &start);
+        assert_eq!(game.outcome(), Outcome::Draw);
+        assert_eq!(game.to_move(), None);
+        assert_eq!(game.play(Move::Drop(3)), Err(MoveError::GameOver));
+
+        assert!(Game::from_moves(Board::new(), "12p1", Controller::Human, Controller::Human).is_err());
+    }
+}
@@ -1,54 +1,251 @@
-
 mod board;
 mod screen;
 mod analysis;
+mod solver;
+mod transposition;
+mod options;
+mod random;
+mod engine;
+mod commands;
+mod game;
+mod rules;
+mod eval;
+mod weights;
+mod tune;
+mod threats;
+mod mcts;
 
-use std::sync::mpsc;
-
-use board::Board;
+use board::{Board, Dimensions, Move, Player, move_offset};
 use screen::ScreenManager;
-use analysis::spawn_analysis_thread;
+use analysis::{Analysis, Config, Method};
+use engine::{Controller, Engine, Level};
+use commands::Command;
+use game::{Game, Outcome};
+use random::Rng;
+use rules::Rules;
+use eval::Evaluator;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().is_some_and(|arg| arg == "tune") {
+        return tune(&args[1..]);
+    }
+    let options = match options::parse(args.iter().map(|s| s.as_str())) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{msg}");
+            std::process::exit(1);
+        }
+    };
+
     let screen = ScreenManager::new();
-    let mut board = Board::new();
+    let mut game = Game::new(Board::empty(options.dimensions, options.rules), options.red, options.yellow);
+
+    screen.update_board(game.board().clone());
+
+    // Humans get the analysis going by the weights they asked for.
+    let default_evaluator = eval::with_weights(eval::DEFAULT, options.weights);
+    let config = Config { hash_megabytes: options.hash_megabytes, threads: options.threads, lines: options.lines };
+    let controller = game.to_move().map(|player| game.controller(player));
+    let analysis = Analysis::new(screen.clone(), game.board().clone(), evaluator(controller, default_evaluator), method(controller), config);
+
+    let seed = options.seed.unwrap_or_else(Rng::seed_from_time);
+    let mut engine = Engine::new(seed);
+
+    if options.dimensions != Dimensions::STANDARD {
+        screen.output_line(format!("Playing on a {} board.", options.dimensions));
+    }
+    if options.rules.name() != rules::Standard.name() {
+        screen.output_line(format!("Playing {} rules.", options.rules));
+    }
+
+    for player in [Player::Red, Player::Yellow] {
+        if let Controller::Engine(difficulty) = game.controller(player) {
+            screen.output_line(format!("{player:?} is played by the engine ({difficulty}). Seed {seed}."));
+        }
+    }
 
-    screen.update_board(board.clone());
+    loop {
+        screen.update_board(game.board().clone());
 
-    let (analysis_send, analysis_receive) = mpsc::channel();
-    let _analysis_thread = spawn_analysis_thread(screen.clone(), board.clone(), analysis_receive);
+        let to_move = game.to_move();
 
-    while let Some(player) = board.next_to_move() {
-        screen.update_board(board.clone());
-        screen.output_line(format!("{:?} to move. Input [1-7].", player));
+        if let Some(player) = to_move {
+            if let Controller::Engine(difficulty) = game.controller(player) {
+                screen.output_line(format!("{player:?} ({}) is thinking...", difficulty.level));
+
+                let m = engine.choose(game.board(), &difficulty, &analysis, options.move_time);
+                game.play(m).expect("engine plays legal moves");
+
+                screen.output_line(format!("{player:?} ({}) plays {m}", difficulty.level));
+                update_analysis(&analysis, &game, default_evaluator, options.ponder);
+                continue;
+            }
+
+            let threatened = game.board().winning_moves(player.opponent());
+            if !threatened.is_empty() {
+                screen.output_line(format!("{:?} threatens {}.", player.opponent(), describe_moves(&threatened)));
+            }
+
+            let move_help = game.board().rules().move_help(game.dimensions().width);
+            screen.output_line(format!("{player:?} to move. Input {move_help}, or help."));
+        }
+        else {
+            match game.outcome() {
+                Outcome::Win(player) => screen.output_line(format!("Game Over.\n{player:?} WINS!")),
+                _ => screen.output_line("Game Over.\nIt's a draw.".to_string()),
+            }
+
+            screen.output_line("Press [ENTER] to leave, or u to undo".into());
+        }
 
         let buf = screen.read_line();
 
-        let i = match buf.trim().parse::<i32>() {
-            Ok(i) => i,
-            Err(_) => {
-                screen.output_line(format!("Bad input, try again"));
+        if to_move.is_none() && buf.trim().is_empty() {
+            break;
+        }
+
+        match commands::parse(&buf) {
+            Ok(Command::Play(m)) => {
+                if let Err(err) = game.play(m) {
+                    screen.output_line(err.to_string());
+                    continue;
+                }
+            }
+            Ok(Command::Undo) => {
+                if !game.undo() {
+                    screen.output_line("Nothing to undo".into());
+                    continue;
+                }
+
+                // Back to a human's turn, or the engine would just play the same thing again.
+                while engine_to_move(&game) && game.undo() {}
+                screen.output_line(format!("Back to move {}.", game.ply()));
+            }
+            Ok(Command::Redo) => {
+                if !game.redo() {
+                    screen.output_line("Nothing to redo".into());
+                    continue;
+                }
+
+                while engine_to_move(&game) && game.redo() {}
+                screen.output_line(format!("Forward to move {}.", game.ply()));
+            }
+            Ok(Command::Jump(ply)) => {
+                if let Err(msg) = game.jump_to(ply) {
+                    screen.output_line(msg);
+                    continue;
+                }
+                screen.output_line(format!("Jumped to move {}: {}", game.ply(), game.to_moves()));
+            }
+            Ok(Command::Load(moves)) => {
+                match Game::from_moves(game.start().clone(), &moves, game.controller(Player::Red), game.controller(Player::Yellow)) {
+                    Ok(loaded) => game = loaded,
+                    Err(err) => {
+                        // Point at the move that broke it.
+                        screen.output_line(format!("Can't load that. {err}\n  {moves}\n  {}^", " ".repeat(move_offset(&moves, err.ply()))));
+                        continue;
+                    }
+                }
+                screen.output_line(format!("Loaded {}.", game.to_moves()));
+            }
+            Ok(Command::Moves) => {
+                screen.output_line(format!("Moves: {}", game.to_moves()));
                 continue;
             }
-        };
+            Ok(Command::Set(side, controller)) => {
+                game.set_controller(side, controller);
+                match controller {
+                    Controller::Human => screen.output_line(format!("{side:?} is played by a human.")),
+                    Controller::Engine(difficulty) => screen.output_line(format!("{side:?} is played by the engine ({difficulty}).")),
+                }
 
-        board = match board.play(i - 1, player, true) { // Subtract 1 to get to board coordinates
-            Ok(next) => next,
+                // Falls through in case the evaluator changed.
+            }
+            Ok(Command::Seed(seed)) => {
+                engine.reseed(seed);
+                screen.output_line(format!("Seed {seed}."));
+                continue;
+            }
+            Ok(Command::Help) => {
+                screen.output_line(commands::HELP.to_string());
+                continue;
+            }
             Err(msg) => {
-                screen.output_line(format!("{msg}"));
+                screen.output_line(msg);
                 continue;
             }
-        };
+        }
 
-        analysis_send.send(board.clone()).expect("Sends");
+        // Every change to the history lands here.
+        update_analysis(&analysis, &game, default_evaluator, options.ponder);
     }
+}
 
-    screen.update_board(board.clone());
-    match board.winner() {
-        Some(player) => screen.output_line(format!("Game Over.\n{player:?} WINS!")),
-        None => screen.output_line(format!("Game Over.\nIt's a draw.")),
+// Points the analysis at the current position. If a human is to move against an engine
+// that gets its moves from the analysis, it ponders for that engine instead, on the reply
+// it expects. Not for shallow engines, which search each move afresh in a table of their
+// own (see analysis::Link::answer), so there'd be nothing in it for them. They still lend
+// the human their evaluator, or the analysis table would get wiped every time the turn
+// changes hands.
+fn update_analysis(analysis: &Analysis, game: &Game, default: &'static dyn Evaluator, ponder: bool) {
+    let board = game.board().clone();
+    let Some(player) = game.to_move()
+        else { return analysis.set_root(board, default, Method::Minimax) };
+
+    match (game.controller(player), game.controller(player.opponent())) {
+        (Controller::Human, engine @ Controller::Engine(difficulty)) if ponder && matches!(difficulty.level, Level::Perfect | Level::Mcts) => {
+            analysis.ponder(board, evaluator(Some(engine), default), method(Some(engine)));
+        }
+        (Controller::Human, engine @ Controller::Engine(difficulty)) if matches!(difficulty.level, Level::Shallow | Level::Perfect | Level::Mcts) => {
+            analysis.set_root(board, evaluator(Some(engine), default), method(Some(engine)));
+        }
+        (controller, _) => analysis.set_root(board, evaluator(Some(controller), default), method(Some(controller))),
     }
+}
+
+// What the analysis should go by. The engine to move asks it for moves, so it gets its own
+// evaluator, humans get the default unless they're up against one (see update_analysis).
+fn evaluator(controller: Option<Controller>, default: &'static dyn Evaluator) -> &'static dyn Evaluator {
+    match controller {
+        Some(Controller::Engine(difficulty)) => difficulty.evaluator,
+        _ => default,
+    }
+}
+
+// Monte Carlo if that's what the engine to move plays by, otherwise the usual search.
+fn method(controller: Option<Controller>) -> Method {
+    match controller {
+        Some(Controller::Engine(difficulty)) if difficulty.level == Level::Mcts => Method::Mcts(difficulty.playouts),
+        _ => Method::Minimax,
+    }
+}
+
+// Like "column 5" or "column 2 and pop 5".
+fn describe_moves(moves: &[Move]) -> String {
+    let described: Vec<String> = moves.iter()
+        .map(|m| match m {
+            Move::Drop(col) => format!("column {}", col + 1),
+            Move::Pop(col) => format!("pop {}", col + 1),
+        })
+        .collect();
+
+    described.join(" and ")
+}
+
+// Runs headless, no board to look at.
+fn tune(args: &[String]) {
+    let result = tune::parse(args.iter().map(|s| s.as_str()))
+        .and_then(|tuning| tune::run(&tuning, &mut |line| println!("{line}")));
+
+    if let Err(msg) = result {
+        eprintln!("{msg}");
+        std::process::exit(1);
+    }
+}
 
-    screen.output_line("Press [ENTER] to leave".into());
-    screen.read_line();
+fn engine_to_move(game: &Game) -> bool {
+    game.to_move()
+        .is_some_and(|player| game.controller(player) != Controller::Human)
 }
@@ -0,0 +1,333 @@
+use std::fmt;
+
+use crate::{board::{Board, Move, Player}, random::Rng};
+
+// Monte Carlo tree search, UCT flavored. Rather than scoring positions it plays lots of
+// games out to the end and goes by how they turned out, so it makes a different kind of
+// opponent than the minimax search, and a second opinion on it.
+
+// How hard UCT looks at moves it hasn't tried much. The textbook sqrt(2).
+const EXPLORATION: f64 = std::f64::consts::SQRT_2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Playout {
+    Random,  // Any legal move
+    Heuristic,  // Wins when it can, blocks when it has to, otherwise random
+}
+
+impl fmt::Display for Playout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Playout::Random => "random",
+            Playout::Heuristic => "heuristic",
+        })
+    }
+}
+
+pub fn parse_playout(name: &str) -> Result<Playout, String> {
+    match name {
+        "random" => Ok(Playout::Random),
+        "heuristic" => Ok(Playout::Heuristic),
+        _ => Err(format!("Unknown playouts: {name} (should be random or heuristic)")),
+    }
+}
+
+// How big a tree fits in that much memory, roughly. Nodes hold their board and moves.
+pub fn max_nodes(megabytes: usize) -> usize {
+    (megabytes << 20) / (std::mem::size_of::<Node>() + 64)
+}
+
+// How one of the root's moves is doing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveStats {
+    pub m: Move,
+    pub visits: u32,
+    pub win_rate: f64,  // For the player to move at the root, draws count half
+}
+
+struct Node {
+    board: Board,
+    m: Option<Move>,  // What got here, None at the root
+    mover: Option<Player>,  // Who played it
+    parent: usize,
+    depth: i32,  // Plies below the root
+    children: Vec<usize>,
+    untried: Vec<Move>,
+    visits: u32,
+    wins: f64,  // For the mover, draws count half
+}
+
+impl Node {
+    fn new(board: Board, m: Option<Move>, mover: Option<Player>, parent: usize, depth: i32) -> Node {
+        let untried = board.legal_moves();
+        Node { board, m, mover, parent, depth, children: vec![], untried, visits: 0, wins: 0.0 }
+    }
+}
+
+pub struct Tree {
+    nodes: Vec<Node>,  // The root is 0
+    max_nodes: usize,
+    playout: Playout,
+    rng: Rng,
+    depth: i32,  // Deepest node so far
+}
+
+impl Tree {
+    // Only `root_moves` get looked at from the root, if given. The tree stops growing at
+    // max_nodes, but playouts carry on.
+    pub fn new(root: Board, root_moves: Option<&[Move]>, playout: Playout, max_nodes: usize, seed: u64) -> Tree {
+        let mut node = Node::new(root, None, None, 0, 0);
+        if let Some(only) = root_moves {
+            node.untried.retain(|m| only.contains(m));
+        }
+
+        Tree { nodes: vec![node], max_nodes: max_nodes.max(1), playout, rng: Rng::new(seed), depth: 0 }
+    }
+
+    pub fn playouts(&self) -> u64 {
+        self.nodes[0].visits as u64
+    }
+
+    pub fn depth(&self) -> i32 {
+        self.depth
+    }
+
+    // The part of the tree under `board` as a tree of its own, with everything else thrown
+    // away. The stats come along, so the search carries on where it left off. None if
+    // `board` isn't in the tree.
+    pub fn reroot(self, board: &Board, playout: Playout) -> Option<Tree> {
+        let start = self.nodes.iter().position(|node| node.board == *board)?;
+        let base = self.nodes[start].depth;
+
+        // Breadth first, so parents keep coming before their children.
+        let mut order = vec![start];
+        let mut i = 0;
+        while i < order.len() {
+            order.extend_from_slice(&self.nodes[order[i]].children);
+            i += 1;
+        }
+
+        let mut new_index = vec![usize::MAX; self.nodes.len()];
+        for (new, &old) in order.iter().enumerate() {
+            new_index[old] = new;
+        }
+
+        let mut old_nodes: Vec<Option<Node>> = self.nodes.into_iter().map(Some).collect();
+        let nodes: Vec<Node> = order.iter()
+            .map(|&old| {
+                let mut node = old_nodes[old].take().expect("each node once");
+                node.parent = if old == start { 0 } else { new_index[node.parent] };
+                node.children.iter_mut().for_each(|child| *child = new_index[*child]);
+                node.depth -= base;
+                node
+            })
+            .collect();
+
+        let depth = nodes.iter().map(|node| node.depth).max().unwrap_or(0);
+        Some(Tree { nodes, playout, depth, ..self })
+    }
+
+    // Select, expand, play out, back up. `count` times over.
+    pub fn run(&mut self, count: u32) {
+        for _ in 0..count {
+            let leaf = self.select();
+            let leaf = self.expand(leaf);
+            let winner = self.play_out(self.nodes[leaf].board.clone());
+            self.back_up(leaf, winner);
+        }
+    }
+
+    // The most visited move, which is the one UCT trusts most.
+    pub fn best_move(&self) -> Option<Move> {
+        self.nodes[0].children.iter()
+            .map(|&child| &self.nodes[child])
+            .max_by_key(|node| node.visits)
+            .and_then(|node| node.m)
+    }
+
+    // The most visited move from `board`, if it's in the tree and has been looked at.
+    pub fn best_move_at(&self, board: &Board) -> Option<Move> {
+        let node = self.nodes.iter().find(|node| node.board == *board)?;
+
+        node.children.iter()
+            .map(|&child| &self.nodes[child])
+            .max_by_key(|child| child.visits)
+            .and_then(|child| child.m)
+    }
+
+    // The root's moves, left to right.
+    pub fn stats(&self) -> Vec<MoveStats> {
+        let mut stats: Vec<MoveStats> = self.nodes[0].children.iter()
+            .map(|&child| &self.nodes[child])
+            .map(|node| MoveStats {
+                m: node.m.expect("children have moves"),
+                visits: node.visits,
+                win_rate: node.wins / node.visits.max(1) as f64,
+            })
+            .collect();
+
+        stats.sort_by_key(|stats| stats.m.to_index());
+        stats
+    }
+
+    // Down the tree by UCT until a node with moves left to try, or the end of the game.
+    fn select(&self) -> usize {
+        let mut index = 0;
+
+        loop {
+            let node = &self.nodes[index];
+            if !node.untried.is_empty() || node.children.is_empty() {
+                return index;
+            }
+
+            let log_visits = (node.visits as f64).ln();
+            index = *node.children.iter()
+                .max_by(|&&a, &&b| self.uct(a, log_visits).total_cmp(&self.uct(b, log_visits)))
+                .expect("has children");
+        }
+    }
+
+    fn uct(&self, index: usize, log_parent_visits: f64) -> f64 {
+        let node = &self.nodes[index];
+        let visits = node.visits as f64;
+        node.wins / visits + EXPLORATION * (log_parent_visits / visits).sqrt()
+    }
+
+    // Adds one of the untried moves, unless the tree is full. Returns the node to play
+    // out from.
+    fn expand(&mut self, index: usize) -> usize {
+        if self.nodes[index].untried.is_empty() || self.nodes.len() >= self.max_nodes {
+            return index;
+        }
+
+        let untried = &mut self.nodes[index].untried;
+        let m = untried.swap_remove(self.rng.below(untried.len()));
+
+        let board = &self.nodes[index].board;
+        let player = board.next_to_move().expect("game is not over");
+        let child = board.play_move(m, player, false).expect("legal");
+
+        let child_index = self.nodes.len();
+        let depth = self.nodes[index].depth + 1;
+        self.nodes.push(Node::new(child, Some(m), Some(player), index, depth));
+        self.nodes[index].children.push(child_index);
+
+        self.depth = self.depth.max(self.nodes[child_index].depth);
+
+        child_index
+    }
+
+    // Plays the game out, returning the winner (None for a draw).
+    fn play_out(&mut self, mut board: Board) -> Option<Player> {
+        // With pops a game can go on forever. Call it a draw well before then.
+        for _ in 0..4 * board.dimensions().cells() {
+            let Some(player) = board.next_to_move()
+                else { return board.winner() };
+
+            let next = board.next_boards();
+            board = match self.playout {
+                Playout::Random => next[self.rng.below(next.len())].clone(),
+                Playout::Heuristic => self.heuristic_pick(next, player),
+            };
+        }
+
+        None
+    }
+
+    fn heuristic_pick(&mut self, mut next: Vec<Board>, player: Player) -> Board {
+        if let Some(win) = next.iter().position(|board| board.winner() == Some(player)) {
+            return next.swap_remove(win);
+        }
+
+        // Anything that doesn't hand them a win, if there is anything.
+        let safe: Vec<usize> = (0..next.len())
+            .filter(|&i| next[i].winning_moves(player.opponent()).is_empty())
+            .collect();
+
+        let pick = match safe.is_empty() {
+            true => self.rng.below(next.len()),
+            false => safe[self.rng.below(safe.len())],
+        };
+
+        next.swap_remove(pick)
+    }
+
+    fn back_up(&mut self, mut index: usize, winner: Option<Player>) {
+        loop {
+            let node = &mut self.nodes[index];
+            node.visits += 1;
+            node.wins += match winner {
+                None => 0.5,
+                Some(player) if Some(player) == node.mover => 1.0,
+                Some(_) => 0.0,
+            };
+
+            if index == 0 {
+                break;
+            }
+            index = node.parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_add_up() {
+        let mut tree = Tree::new(Board::new(), None, Playout::Random, 1000, 1);
+        tree.run(500);
+
+        let stats = tree.stats();
+        assert_eq!(tree.playouts(), 500);
+        assert_eq!(stats.len(), 7);
+        assert_eq!(stats.iter().map(|s| s.visits as u64).sum::<u64>(), 500);
+        assert!(stats.iter().all(|s| (0.0..=1.0).contains(&s.win_rate)));
+        assert!(tree.nodes.len() <= 1000);
+
+        // A full tree keeps on playing out.
+        tree.run(1000);
+        assert_eq!(tree.playouts(), 1500);
+        assert_eq!(tree.nodes.len(), 1000);
+    }
+
+    #[test]
+    fn finds_wins_and_blocks() {
+        for playout in [Playout::Random, Playout::Heuristic] {
+            // Red wins in column 1.
+            let mut tree = Tree::new(Board::from_moves("121212").unwrap(), None, playout, 100_000, 2);
+            tree.run(3000);
+            assert_eq!(tree.best_move(), Some(Move::Drop(0)), "{playout}");
+
+            // Yellow has to stop it.
+            let mut tree = Tree::new(Board::from_moves("12121").unwrap(), None, playout, 100_000, 3);
+            tree.run(3000);
+            assert_eq!(tree.best_move(), Some(Move::Drop(0)), "{playout}");
+        }
+
+        let mut tree = Tree::new(Board::new(), Some(&[Move::Drop(2)]), Playout::Random, 100, 4);
+        tree.run(50);
+        assert_eq!(tree.stats().iter().map(|s| s.m).collect::<Vec<_>>(), vec![Move::Drop(2)]);
+
+        assert_eq!(parse_playout("random"), Ok(Playout::Random));
+        assert!(parse_playout("smart").is_err());
+    }
+
+    #[test]
+    fn keeps_the_subtree() {
+        let mut tree = Tree::new(Board::new(), None, Playout::Random, 10_000, 5);
+        tree.run(2000);
+        let visits = tree.stats().iter().find(|stats| stats.m == Move::Drop(3)).unwrap().visits;
+
+        let after = Board::from_moves("4").unwrap();
+        let mut kept = tree.reroot(&after, Playout::Heuristic).unwrap();
+        assert_eq!(kept.playouts(), visits as u64);
+        assert!(kept.nodes.iter().all(|node| after.can_reach(&node.board)));
+        assert!(kept.nodes.iter().enumerate().skip(1).all(|(i, node)| node.parent < i && kept.nodes[node.parent].children.contains(&i)));
+
+        kept.run(100);
+        assert_eq!(kept.playouts(), visits as u64 + 100);
+        assert!(kept.reroot(&Board::from_moves("3").unwrap(), Playout::Random).is_none());
+    }
+}
@@ -0,0 +1,242 @@
+use std::time::Duration;
+
+use crate::{board::Dimensions, rules::{self, Rules}, engine::{Controller, parse_controller, parse_depth, parse_blunder_rate}, eval, mcts::parse_playout, weights::Weights};
+
+// Command line options.
+
+pub const USAGE: &str = "\
+Usage: connect_four [options]
+
+Options:
+    --red <player>             Who plays Red (default human)
+    --yellow <player>          Who plays Yellow (default human)
+    --move-time <seconds>      How long the engine thinks per move (default 2)
+    --depth <n>                Search depth for shallow engines, 1 to 10 (default 4)
+    --blunder <rate>           Chance an engine plays a random move, 0 to 1 (default 0)
+    --playouts <kind>          How mcts engines play games out: heuristic or random
+                               (default heuristic)
+    --red-eval <name>          Evaluator for a Red engine: default, threats, parity, or center
+    --yellow-eval <name>       Evaluator for a Yellow engine (default default)
+    --weights <file>           Weights for the evaluators, see weights/default.toml
+    --red-weights <file>       Weights for a Red engine only (default --weights)
+    --yellow-weights <file>    Weights for a Yellow engine only
+    --seed <n>                 Seed for engine randomness (default from the clock)
+    --hash <MB>                Memory for the analysis transposition table (default 64)
+    --threads <n>              Threads for the analysis search (default 1)
+    --lines <n>                How many of the best lines the analysis shows (default 1)
+    --no-ponder                Don't let perfect and mcts engines think on a human's time
+    --size <width>x<height>    Board size, up to 9x12 (default 7x6)
+    --connect <n>              How many in a row it takes to win (default 4)
+    --variant <rules>          standard, popout, or cylinder (default standard)
+    --help                     Show this message
+
+Players are human, random, greedy, shallow, perfect, engine (same as perfect), or mcts.
+
+To tune evaluator weights by self-play instead, see connect_four tune --help.";
+
+// More than anyone has cores for.
+const MAX_THREADS: usize = 256;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub red: Controller,
+    pub yellow: Controller,
+    pub move_time: Duration,
+    pub hash_megabytes: usize,
+    pub threads: usize,
+    pub lines: usize,
+    pub ponder: bool,
+    pub seed: Option<u64>,
+    pub dimensions: Dimensions,
+    pub rules: &'static dyn Rules,
+    pub weights: Weights,  // For analysing human moves, and engines without their own
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            red: Controller::Human,
+            yellow: Controller::Human,
+            move_time: Duration::from_secs(2),
+            hash_megabytes: 64,
+            threads: 1,
+            lines: 1,
+            ponder: true,
+            seed: None,
+            dimensions: Dimensions::STANDARD,
+            rules: &rules::Standard,
+            weights: Weights::DEFAULT,
+        }
+    }
+}
+
+// "8x7" into width and height.
+pub fn parse_size(size: &str) -> Result<(i32, i32), String> {
+    let parsed = size.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+
+    parsed.ok_or(format!("Bad board size: {size} (should be like 7x6)"))
+}
+
+pub fn value<'a>(flag: &str, args: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, String> {
+    args.next().ok_or(format!("{flag} needs a value"))
+}
+
+// Args exclude the program name. Err holds a message for the user.
+pub fn parse<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    // These apply to whichever players end up being engines, so wait until the end.
+    let mut depth = None;
+    let mut blunder_rate = None;
+    let mut playouts = None;
+    let mut evaluators = [None, None];
+    let mut side_weights = [None, None];
+
+    // Checked together at the end, since whether a length fits depends on the size.
+    let Dimensions { mut width, mut height, mut connect } = options.dimensions;
+
+    while let Some(arg) = args.next() {
+        match arg {
+            "--red" => options.red = parse_controller(value(arg, &mut args)?)?,
+            "--yellow" => options.yellow = parse_controller(value(arg, &mut args)?)?,
+            "--depth" => depth = Some(parse_depth(value(arg, &mut args)?)?),
+            "--blunder" => blunder_rate = Some(parse_blunder_rate(value(arg, &mut args)?)?),
+            "--playouts" => playouts = Some(parse_playout(value(arg, &mut args)?)?),
+            "--red-eval" => evaluators[0] = Some(eval::by_name(value(arg, &mut args)?)?),
+            "--yellow-eval" => evaluators[1] = Some(eval::by_name(value(arg, &mut args)?)?),
+            "--weights" => options.weights = Weights::load(value(arg, &mut args)?)?,
+            "--red-weights" => side_weights[0] = Some(Weights::load(value(arg, &mut args)?)?),
+            "--yellow-weights" => side_weights[1] = Some(Weights::load(value(arg, &mut args)?)?),
+            "--seed" => {
+                let seed = value(arg, &mut args)?;
+                options.seed = Some(seed.parse().map_err(|_| format!("Bad seed: {seed}"))?);
+            }
+            "--move-time" => {
+                let seconds = value(arg, &mut args)?;
+                options.move_time = match seconds.parse::<f64>() {
+                    Ok(s) if s > 0.0 && s.is_finite() => Duration::from_secs_f64(s),
+                    _ => return Err(format!("Bad move time: {seconds}")),
+                };
+            }
+            "--hash" => {
+                let mb = value(arg, &mut args)?;
+                options.hash_megabytes = match mb.parse() {
+                    Ok(mb) if mb > 0 => mb,
+                    _ => return Err(format!("Bad table size: {mb}")),
+                };
+            }
+            "--threads" => {
+                let n = value(arg, &mut args)?;
+                options.threads = match n.parse() {
+                    Ok(n) if (1..=MAX_THREADS).contains(&n) => n,
+                    _ => return Err(format!("Bad thread count: {n} (should be 1 to {MAX_THREADS})")),
+                };
+            }
+            "--lines" => {
+                let n = value(arg, &mut args)?;
+                options.lines = match n.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("Bad line count: {n}")),
+                };
+            }
+            "--no-ponder" => options.ponder = false,
+            "--size" => (width, height) = parse_size(value(arg, &mut args)?)?,
+            "--connect" => {
+                let n = value(arg, &mut args)?;
+                connect = n.parse().map_err(|_| format!("Bad connect length: {n}"))?;
+            }
+            "--variant" => options.rules = rules::by_name(value(arg, &mut args)?)?,
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ => return Err(format!("Unknown option: {arg}\n\n{USAGE}")),
+        }
+    }
+
+    options.dimensions = Dimensions::new(width, height, connect)?;
+
+    let sides = [&mut options.red, &mut options.yellow].into_iter().zip(evaluators).zip(side_weights);
+    for ((controller, evaluator), weights) in sides {
+        if let Controller::Engine(difficulty) = controller {
+            difficulty.depth = depth.unwrap_or(difficulty.depth);
+            difficulty.blunder_rate = blunder_rate.unwrap_or(difficulty.blunder_rate);
+            difficulty.playouts = playouts.unwrap_or(difficulty.playouts);
+            let evaluator = evaluator.unwrap_or(difficulty.evaluator);
+            difficulty.evaluator = eval::with_weights(evaluator, weights.unwrap_or(options.weights));
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{engine::{Difficulty, Level}, mcts::Playout};
+
+    #[test]
+    fn parses_hash() {
+        assert_eq!(parse([]), Ok(Options::default()));
+        assert_eq!(parse(["--hash", "128"]).map(|o| o.hash_megabytes), Ok(128));
+        assert!(parse(["--hash"]).is_err());
+        assert!(parse(["--hash", "0"]).is_err());
+        assert!(parse(["--bogus"]).is_err());
+
+        assert_eq!(parse(["--threads", "16"]).map(|o| o.threads), Ok(16));
+        assert!(parse(["--threads", "0"]).is_err());
+        assert_eq!(parse(["--lines", "3"]).map(|o| o.lines), Ok(3));
+        assert!(parse(["--lines", "none"]).is_err());
+        assert_eq!(parse(["--no-ponder"]).map(|o| o.ponder), Ok(false));
+    }
+
+    #[test]
+    fn parses_players() {
+        let options = parse(["--yellow", "engine", "--move-time", "0.5"]).unwrap();
+        assert_eq!((options.red, options.yellow), (Controller::Human, Controller::Engine(Difficulty::default())));
+        assert_eq!(options.move_time, Duration::from_millis(500));
+
+        assert!(parse(["--red", "robot"]).is_err());
+        assert!(parse(["--move-time", "-1"]).is_err());
+    }
+
+    #[test]
+    fn parses_difficulty() {
+        let options = parse(["--depth", "6", "--red", "shallow", "--blunder", "0.1", "--seed", "42"]).unwrap();
+        assert_eq!(options.red, Controller::Engine(Difficulty { level: Level::Shallow, depth: 6, blunder_rate: 0.1, ..Difficulty::default() }));
+        assert_eq!(options.yellow, Controller::Human);
+        assert_eq!(options.seed, Some(42));
+
+        // Each side its own evaluator, for playing them against each other.
+        let options = parse(["--red", "shallow", "--yellow", "perfect", "--red-eval", "threats", "--yellow-eval", "center"]).unwrap();
+        assert!(matches!(options.red, Controller::Engine(difficulty) if difficulty.evaluator.name() == "threats"));
+        assert!(matches!(options.yellow, Controller::Engine(difficulty) if difficulty.evaluator.name() == "center"));
+        assert!(parse(["--red-eval", "magic"]).is_err());
+
+        let options = parse(["--red", "greedy", "--red-weights", "weights/default.toml", "--weights", "weights/default.toml"]).unwrap();
+        assert!(matches!(options.red, Controller::Engine(difficulty) if *difficulty.evaluator == *eval::DEFAULT));
+        assert_eq!(options.weights, Weights::DEFAULT);
+        assert!(parse(["--weights", "no/such/file.toml"]).is_err());
+
+        let options = parse(["--yellow", "mcts", "--playouts", "random"]).unwrap();
+        assert!(matches!(options.yellow, Controller::Engine(Difficulty { level: Level::Mcts, playouts: Playout::Random, .. })));
+        assert!(parse(["--playouts", "smart"]).is_err());
+
+        assert!(parse(["--blunder", "1.5"]).is_err());
+        assert!(parse(["--seed", "x"]).is_err());
+    }
+
+    #[test]
+    fn parses_dimensions() {
+        let options = parse(["--connect", "5", "--size", "9x7"]).unwrap();
+        assert_eq!(options.dimensions, Dimensions { width: 9, height: 7, connect: 5 });
+        assert_eq!(parse(["--size", "6x5"]).map(|o| o.dimensions.connect), Ok(4));
+
+        assert!(parse(["--size", "7"]).is_err());
+        assert!(parse(["--size", "12x6"]).is_err());
+        assert!(parse(["--size", "4x4", "--connect", "5"]).is_err());
+
+        assert_eq!(parse(["--variant", "popout"]).map(|o| o.rules.name()), Ok("popout"));
+        assert_eq!(parse(["--variant", "cylinder"]).map(|o| o.rules.name()), Ok("cylinder"));
+        assert!(parse(["--variant", "pop10"]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+
+use crate::board::{Board, Move, MoveError, Player};
+
+pub mod standard;
+pub mod popout;
+pub mod cylinder;
+
+pub use standard::Standard;
+pub use popout::PopOut;
+pub use cylinder::Cylinder;
+
+// A rule set. Boards carry theirs, so anything holding a board can ask what's legal on it
+// without knowing which variant it is.
+//
+// The board does the bit twiddling (dropping, popping, finding lines), rules decide which
+// of those make a game. The default methods are the standard rules, so a variant only
+// overrides what it changes.
+pub trait Rules: Sync {
+    fn name(&self) -> &'static str;
+
+    // Whether lines carry on round the left and right edges. Decides which lines the
+    // board looks for, see Geometry.
+    fn wraps(&self) -> bool {
+        false
+    }
+
+    // Whether the solver knows how to play these rules.
+    fn solvable(&self) -> bool {
+        false
+    }
+
+    // Every move that could ever be legal, given the columns. Whether one actually is
+    // depends on the position, see `apply`.
+    fn moves(&self, columns: &[i32]) -> Vec<Move> {
+        columns.iter().map(|col| Move::Drop(*col)).collect()
+    }
+
+    // How to type a move, for the prompt.
+    fn move_help(&self, width: i32) -> String {
+        format!("[1-{width}]")
+    }
+
+    // Plays `m` for `player`. Whose turn it is has already been checked.
+    fn apply(&self, board: &Board, m: Move, player: Player) -> Result<Board, MoveError> {
+        match m {
+            Move::Drop(col) => board.drop_piece(col, player),
+            Move::Pop(_) => Err(MoveError::PopNotAllowed),
+        }
+    }
+
+    // The position before `m` was played to reach `board`, if it could have been.
+    fn undo(&self, board: &Board, m: Move) -> Option<Board> {
+        match m {
+            Move::Drop(col) => board.take_top(col, board.turn().opponent()),
+            Move::Pop(_) => None,
+        }
+    }
+
+    // None if no winner (or game ongoing)
+    fn winner(&self, board: &Board) -> Option<Player> {
+        if board.has_line(Player::Red) {
+            Some(Player::Red)
+        }
+        else if board.has_line(Player::Yellow) {
+            Some(Player::Yellow)
+        }
+        else {
+            None
+        }
+    }
+
+    // None if the game is over.
+    fn next_to_move(&self, board: &Board) -> Option<Player> {
+        if self.winner(board).is_some() || board.is_full() {
+            None
+        }
+        else {
+            Some(board.turn())
+        }
+    }
+
+    // Most plies the game can go on for, None if there's no limit.
+    fn plies_left(&self, board: &Board) -> Option<i32> {
+        Some(board.dimensions().cells() - board.pieces_played())
+    }
+}
+
+// Rules are all singletons, so the name says which one it is.
+impl PartialEq for dyn Rules {
+    fn eq(&self, other: &dyn Rules) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for dyn Rules {}
+
+impl fmt::Debug for dyn Rules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for dyn Rules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// Every rule set there is. New variants go here to be selectable.
+pub const ALL: [&dyn Rules; 3] = [&Standard, &PopOut, &Cylinder];
+
+pub fn by_name(name: &str) -> Result<&'static dyn Rules, String> {
+    let name = match name {
+        "pop" => "popout",
+        "wrap" => "cylinder",
+        _ => name,
+    };
+
+    ALL.into_iter()
+        .find(|rules| rules.name() == name)
+        .ok_or(format!("Unknown variant: {name}"))
+}
@@ -0,0 +1,15 @@
+use super::Rules;
+
+// The left and right edges join up, so lines can run from the last column round to the
+// first. Otherwise standard, though the solver doesn't know about the wrapping.
+pub struct Cylinder;
+
+impl Rules for Cylinder {
+    fn name(&self) -> &'static str {
+        "cylinder"
+    }
+
+    fn wraps(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,134 @@
+use crate::board::{Board, Move, MoveError, Player};
+
+use super::Rules;
+
+// Instead of dropping a piece, you may take one of your own off the bottom of a column,
+// and everything above it drops down one. A full board isn't the end as long as the player
+// to move has something to pop. Positions can repeat, which Game turns into a draw.
+pub struct PopOut;
+
+impl Rules for PopOut {
+    fn name(&self) -> &'static str {
+        "popout"
+    }
+
+    fn moves(&self, columns: &[i32]) -> Vec<Move> {
+        let drops = columns.iter().map(|col| Move::Drop(*col));
+        let pops = columns.iter().map(|col| Move::Pop(*col));
+        drops.chain(pops).collect()
+    }
+
+    fn move_help(&self, width: i32) -> String {
+        format!("[1-{width}], p[1-{width}] to pop")
+    }
+
+    fn apply(&self, board: &Board, m: Move, player: Player) -> Result<Board, MoveError> {
+        match m {
+            Move::Drop(col) => board.drop_piece(col, player),
+            Move::Pop(col) => board.pop_piece(col, player),
+        }
+    }
+
+    fn undo(&self, board: &Board, m: Move) -> Option<Board> {
+        let player = board.turn().opponent();
+        let prev = match m {
+            Move::Drop(col) => board.take_top(col, player)?,
+            Move::Pop(col) => board.push_bottom(col, player)?,
+        };
+
+        // Nobody plays on from a finished game.
+        self.next_to_move(&prev).map(|_| prev)
+    }
+
+    fn winner(&self, board: &Board) -> Option<Player> {
+        match (board.has_line(Player::Red), board.has_line(Player::Yellow)) {
+            // Only a pop can do this, and whoever popped wins.
+            (true, true) => Some(board.turn().opponent()),
+            (true, false) => Some(Player::Red),
+            (false, true) => Some(Player::Yellow),
+            (false, false) => None,
+        }
+    }
+
+    fn next_to_move(&self, board: &Board) -> Option<Player> {
+        let player = board.turn();
+        let can_pop = board.pieces(player) & board.geometry().bottom() != 0;
+
+        if self.winner(board).is_some() || (board.is_full() && !can_pop) {
+            None
+        }
+        else {
+            Some(player)
+        }
+    }
+
+    fn plies_left(&self, _board: &Board) -> Option<i32> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::{Dimensions, SequenceError, Tile, move_offset, moves_to_string, parse_moves};
+
+    #[test]
+    fn pops() {
+        let start = Board::empty(Dimensions::STANDARD, &PopOut);
+        let board = start.play(3, Player::Red, true).unwrap()
+            .play(3, Player::Yellow, true).unwrap()
+            .play(4, Player::Red, true).unwrap();
+
+        // Yellow has nothing on the bottom row to pop.
+        assert_eq!(board.pop(3, Player::Yellow, true), Err(MoveError::NotYourPiece(3)));
+        assert_eq!(board.next_boards().len(), 7);
+
+        let popped = board.play(0, Player::Yellow, true).unwrap()
+            .pop(3, Player::Red, true).unwrap();
+        assert_eq!(popped.tile(0, 3), Tile::Piece(Player::Yellow));
+        assert_eq!(popped.tile(1, 3), Tile::Empty);
+        assert_eq!(popped.next_to_move(), Some(Player::Yellow));
+        assert!(popped.prev_boards().contains(&board.play(0, Player::Yellow, true).unwrap()));
+
+        // Same pieces, different player to move.
+        let yellow_to_move = start.play(0, Player::Red, true).unwrap().play(6, Player::Yellow, true).unwrap()
+            .pop(0, Player::Red, true).unwrap();
+        let red_to_move = start.play(6, Player::Yellow, false).unwrap();
+        assert_eq!(yellow_to_move.next_to_move(), Some(Player::Yellow));
+        assert_eq!(red_to_move.next_to_move(), Some(Player::Red));
+        assert_ne!(yellow_to_move, red_to_move);
+        assert_ne!(yellow_to_move.key(), red_to_move.key());
+
+        assert_eq!(Board::new().pop(3, Player::Red, true), Err(MoveError::PopNotAllowed));
+        assert_eq!(moves_to_string(&parse_moves("4p4 3", 7).unwrap()), "4p43");
+        assert_eq!(parse_moves("4pp", 7), Err(SequenceError::NotAColumn { ply: 2, character: 'p' }));
+        assert_eq!(move_offset("44p3x", 4), 4);
+    }
+
+    #[test]
+    fn pop_completing_both_lines() {
+        // Red pops column 1, dropping a yellow four and a red four into place at once.
+        let mut board = Board::empty(Dimensions::new(4, 5, 4).unwrap(), &PopOut);
+        for m in parse_moves("1223344112", 4).unwrap() {
+            board = board.play_move(m, board.next_to_move().unwrap(), true).unwrap();
+        }
+        assert_eq!(board.winner(), None, "{}", board.display());
+
+        let popped = board.pop(0, Player::Red, true).unwrap();
+        assert_eq!(popped.winner(), Some(Player::Red), "{}", popped.display());
+    }
+
+    #[test]
+    fn full_board() {
+        // Nobody has three in a row on the full board, and Red still has pieces on the
+        // bottom row to pop.
+        let mut board = Board::empty(Dimensions::new(3, 2, 3).unwrap(), &PopOut);
+        for m in parse_moves("123123", 3).unwrap() {
+            board = board.play_move(m, board.next_to_move().unwrap(), true).unwrap();
+        }
+        assert!(board.is_full());
+        assert_eq!(board.next_to_move(), Some(Player::Red));
+        assert_eq!(board.legal_moves(), vec![Move::Pop(0), Move::Pop(2)]);
+        assert_eq!(board.plies_left(), None);
+    }
+}
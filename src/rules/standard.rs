@@ -0,0 +1,14 @@
+use super::Rules;
+
+// Plain Connect Four, of any size. Five-in-a-Row is this with --connect 5.
+pub struct Standard;
+
+impl Rules for Standard {
+    fn name(&self) -> &'static str {
+        "standard"
+    }
+
+    fn solvable(&self) -> bool {
+        true
+    }
+}
@@ -12,7 +12,7 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}, event::{Event, KeyEventKind, KeyCode, KeyEvent, KeyModifiers},
 };
 
-use crate::board::Board;
+use crate::{analysis::{Line, MoveScore}, board::{Board, Move, Player, moves_to_string}, mcts::MoveStats, solver::Solution, threats::{self, Threat}};
 
 type Term = Terminal<CrosstermBackend<Stdout>>;
 
@@ -21,15 +21,23 @@ struct ScreenState {
     input_buffer: String,
     output_buffer: String,
     board: Option<Board>,
-    analyzed_boards: i32,
+    analyzed_boards: u64,
+    nodes_per_second: u64,
+    threads: usize,
     root_score: i32,
-    next_move: i32,
+    next_move: Option<Move>,
+    search_depth: i32,
+    solution: Option<Solution>,
+    mcts: Vec<MoveStats>,  // Empty unless Monte Carlo is doing the analysis
+    move_scores: Vec<(Move, MoveScore)>,
+    lines: Vec<Line>,  // Best first
+    pondering: Option<Move>,  // The reply the analysis is assuming, if any
 }
 
 fn truncate_output(str : String, i: u16) -> String {
     let mut vec = str.lines().rev().take(i as usize).collect::<Vec<_>>();
     vec.reverse();
-    vec.join("\n") + &"\n"
+    vec.join("\n") + "\n"
 }
 
 fn analysis_paragraph(state: &ScreenState) -> String {
@@ -38,14 +46,164 @@ fn analysis_paragraph(state: &ScreenState) -> String {
         None => "???".to_string(),
     };
 
-    format!("current score (naive): {score}\nboards analyzed: {}\ncurrent score (analyzed): {}\nnext move: {}\n", 
-        state.analyzed_boards, state.root_score, state.next_move + 1
-    )
+    let solution = match &state.solution {
+        Some(solution) => solution.to_string(),
+        None => "unknown".to_string(),
+    };
+
+    let next_move = match &state.next_move {
+        Some(m) => m.to_string(),
+        None => "none".to_string(),
+    };
+
+    let threads = match state.threads {
+        1 => "1 thread".to_string(),
+        n => format!("{n} threads"),
+    };
+
+    let mut paragraph = format!("current score (naive): {score}\nboards analyzed: {}\nboards per second: {} ({threads})\ncurrent score (analyzed): {}\nnext move: {next_move}\nsearch depth: {}\nperfect play: {solution}\n",
+        state.analyzed_boards, state.nodes_per_second, state.root_score, state.search_depth
+    );
+
+    if let Some(m) = state.pondering {
+        paragraph += &format!("pondering: expecting {m}\n");
+    }
+
+    if let (Some(board), false) = (analyzed_board(state), state.lines.is_empty()) {
+        paragraph += &lines_paragraph(&board, &state.lines);
+    }
+
+    if let (Some(player), false, None) = (state.board.as_ref().and_then(Board::next_to_move), state.move_scores.is_empty(), state.pondering) {
+        let player = format!("{player:?}").to_lowercase();
+        paragraph += &format!("under the board: for {player}, W/L in plies, scores in tens\n");
+    }
+
+    if !state.mcts.is_empty() {
+        paragraph += &mcts_paragraph(&state.mcts);
+    }
+
+    if let Some(board) = &state.board {
+        paragraph += &threats_paragraph(board);
+    }
+
+    paragraph
+}
+
+// The position the analysis is about. Past the board on screen when pondering.
+fn analyzed_board(state: &ScreenState) -> Option<Board> {
+    let board = state.board.clone()?;
+    match (state.pondering, board.next_to_move()) {
+        (Some(m), Some(player)) => board.play_move(m, player, false).ok(),
+        _ => Some(board),
+    }
+}
+
+// Like "1. 4453 (+120)", scores Red positive like the rest of the panel.
+fn lines_paragraph(board: &Board, lines: &[Line]) -> String {
+    let mut paragraph = "\nbest lines\n".to_string();
+
+    for (i, line) in lines.iter().enumerate() {
+        let score = match line.score {
+            MoveScore::Heuristic(score) if board.next_to_move() == Some(Player::Yellow) => format!("{:+}", -score),
+            MoveScore::Heuristic(score) => format!("{score:+}"),
+            MoveScore::Solved(solution) => solution.to_string().to_lowercase(),
+        };
+        paragraph += &format!("  {}. {} ({score})\n", i + 1, moves_to_string(&line.moves));
+    }
+
+    paragraph
+}
+
+// A score per column, three characters wide to line up under the labels Board::display puts
+// there. For the player to move: W5 wins in 5 plies, L4 loses in 4, = is a draw, and search
+// scores are in tens so they fit. Pops don't get one.
+fn move_scores_row(board: &Board, scores: &[(Move, MoveScore)]) -> String {
+    let Some(player) = board.next_to_move()
+        else { return String::new() };
+
+    let mut row = " ".to_string();
+    for col in 0..board.dimensions().width {
+        let text = match scores.iter().find(|(m, _)| *m == Move::Drop(col)) {
+            None => String::new(),
+            Some((_, MoveScore::Heuristic(score))) => ((*score as f64 / 10.0).round() as i32).clamp(-99, 999).to_string(),
+            Some((_, MoveScore::Solved(solution))) => {
+                let letter = match solution.winner {
+                    None => "=",
+                    Some(winner) if winner == player => "W",
+                    Some(_) => "L",
+                };
+                match (solution.winner, solution.plies) {
+                    (Some(_), 0..=99) => format!("{letter}{}", solution.plies),
+                    _ => letter.to_string(),
+                }
+            }
+        };
+        row += &format!("{text:^3}");
+    }
+
+    row
+}
+
+// A line per move, like "4: 1520 visits, 57% wins".
+fn mcts_paragraph(stats: &[MoveStats]) -> String {
+    let mut paragraph = "\nmonte carlo\n".to_string();
+
+    for stats in stats {
+        paragraph += &format!("  {}: {} visits, {:.0}% wins\n", stats.m, stats.visits, stats.win_rate * 100.0);
+    }
+
+    paragraph
+}
+
+// Like "4 row 3 (odd, playable)", counting from 1 the way the board labels do.
+fn describe_threat(threat: &Threat) -> String {
+    let parity = if threat.odd() { "odd" } else { "even" };
+    let playable = if threat.playable { ", playable" } else { "" };
+    format!("{} row {} ({parity}{playable})", threat.col + 1, threat.row + 1)
+}
+
+fn threats_paragraph(board: &Board) -> String {
+    let mut paragraph = "\nthreats\n".to_string();
+
+    for player in [Player::Red, Player::Yellow] {
+        let threats = threats::threats(board, player);
+        let listed = match threats.is_empty() {
+            true => "none".to_string(),
+            false => threats.iter().map(describe_threat).collect::<Vec<_>>().join(", "),
+        };
+        paragraph += &format!("  {}: {listed}\n", format!("{player:?}").to_lowercase());
+    }
+
+    let zugzwang = match threats::zugzwang(board) {
+        Some(player) => format!("{player:?}").to_lowercase(),
+        None => "unclear".to_string(),
+    };
+    paragraph += &format!("  zugzwang: {zugzwang}\n");
+
+    paragraph
+}
+
+// Width and height of the board's box, which grows with the board. Never smaller than
+// the standard board's, so the analysis box has room too.
+fn board_box_size(board: &Option<Board>) -> (u16, u16) {
+    let (width, height) = board.as_ref()
+        .map(|board| (board.dimensions().width as u16, board.dimensions().height as u16))
+        .unwrap_or((7, 6));
+
+    // 3 characters per column and 2 for the edges, 4 lines for edges, labels and scores,
+    // plus margins.
+    ((3 * width + 10).max(31), (height + 8).max(14))
 }
 
 fn draw(terminal: &mut Term, state: &mut ScreenState) {
     terminal.draw(|f| {
         let full_rect = f.size();
+        let (board_width, board_height) = board_box_size(&state.board);
+
+        // The analysis box grows down past the board if it has to, and everything under
+        // them moves down with it.
+        let analysis_text = analysis_paragraph(state);
+        let top_height = board_height.max(analysis_text.lines().count() as u16 + 4);
 
         let input_rect = Rect::new(2, full_rect.height - 3, full_rect.width - 4, 3);
         let input_zone = Block::default()
@@ -55,30 +213,36 @@ fn draw(terminal: &mut Term, state: &mut ScreenState) {
         f.render_widget(input_paragraph, input_rect.inner(&Margin { vertical: 1, horizontal: 2 }));
 
         if let Some(board) = &state.board {
-            let board_rect = Rect::new(2, 1, 31, 13);
+            let board_rect = Rect::new(2, 1, board_width, board_height);
             let board_zone = Block::default()
-                .title("Board")
+                .title(format!("Board ({})", board.rules()))
                 .borders(Borders::ALL);
-            f.render_widget(board_zone, board_rect.clone());
-
-            let board_paragraph = Paragraph::new(board.display());
+            f.render_widget(board_zone, board_rect);
+
+            // Scores for the position after the expected reply would sit under the wrong board.
+            let scores = match state.pondering {
+                None => &state.move_scores[..],
+                Some(_) => &[],
+            };
+            let board_paragraph = Paragraph::new(board.display() + "\n" + &move_scores_row(board, scores));
             f.render_widget(board_paragraph, board_rect.inner(&Margin {vertical: 2, horizontal: 4}));
         }
 
-        let analysis_rect = Rect::new(34, 1, input_rect.width -32, 13);
+        let analysis_x = board_width + 3;
+        let analysis_rect = Rect::new(analysis_x, 1, full_rect.width.saturating_sub(analysis_x + 2), top_height);
         let analysis_zone = Block::default()
             .title("Analysis")
             .borders(Borders::ALL);
-        f.render_widget(analysis_zone, analysis_rect.clone());
-        let analysis_paragraph = Paragraph::new(analysis_paragraph(&state));
+        f.render_widget(analysis_zone, analysis_rect);
+        let analysis_paragraph = Paragraph::new(analysis_text);
         f.render_widget(analysis_paragraph, analysis_rect.inner(&Margin {vertical: 2, horizontal: 4}));
 
 
-        let output_rect = Rect::new(2, 14, full_rect.width - 4, full_rect.height - 17);
+        let output_rect = Rect::new(2, top_height + 1, full_rect.width - 4, full_rect.height.saturating_sub(top_height + 4));
         let output_zone = Block::default()
             .title("Messages")
             .borders(Borders::ALL);
-        f.render_widget(output_zone, output_rect.clone());
+        f.render_widget(output_zone, output_rect);
         let output_paragraph_rect = output_rect.inner(&Margin {vertical: 1, horizontal: 4});
         state.output_buffer = truncate_output(state.output_buffer.clone(), output_paragraph_rect.height);
         let output_zone = Paragraph::new(state.output_buffer.clone());
@@ -94,7 +258,7 @@ fn draw(terminal: &mut Term, state: &mut ScreenState) {
 // }
 
 fn spawn_tui_thread(receiver: mpsc::Receiver<ScreenUpdate>, input_sender: mpsc::Sender<String>) -> thread::JoinHandle<()> {
-    return thread::spawn(move || {
+    thread::spawn(move || {
         enable_raw_mode().expect("success");
         let mut stdout = io::stdout();
         execute!(stdout, EnterAlternateScreen).expect("success");
@@ -107,8 +271,16 @@ fn spawn_tui_thread(receiver: mpsc::Receiver<ScreenUpdate>, input_sender: mpsc::
             output_buffer: String::new(), 
             board: None, 
             analyzed_boards: 0, 
+            nodes_per_second: 0,
+            threads: 1,
             root_score: 0,
-            next_move: -1,
+            next_move: None,
+            search_depth: 0,
+            solution: None,
+            mcts: vec![],
+            move_scores: vec![],
+            lines: vec![],
+            pondering: None,
         };
 
         draw(&mut terminal, &mut state);
@@ -121,6 +293,7 @@ fn spawn_tui_thread(receiver: mpsc::Receiver<ScreenUpdate>, input_sender: mpsc::
                 ScreenUpdate::UpdateBoard(board) => state.board = Some(board),
                 ScreenUpdate::UpdateOutput(output) => state.output_buffer += &output,
                 ScreenUpdate::AnalysisCount(count) => state.analyzed_boards = count,
+                ScreenUpdate::Throughput(per_second, threads) => (state.nodes_per_second, state.threads) = (per_second, threads),
                 ScreenUpdate::CrosstermEvent(Event::Key(KeyEvent { 
                     code: KeyCode::Char(c), modifiers, kind: KeyEventKind::Press, .. 
                 })) => {  
@@ -133,7 +306,7 @@ fn spawn_tui_thread(receiver: mpsc::Receiver<ScreenUpdate>, input_sender: mpsc::
                     code: KeyCode::Enter, kind: KeyEventKind::Press, .. 
                 })) => {
                     state.input_buffer += "\n";
-                    state.output_buffer += &"> ";
+                    state.output_buffer += "> ";
                     state.output_buffer += &state.input_buffer;
                     input_sender.send(state.input_buffer.clone()).expect("sends");
                     state.input_buffer.clear();
@@ -141,13 +314,19 @@ fn spawn_tui_thread(receiver: mpsc::Receiver<ScreenUpdate>, input_sender: mpsc::
                 ScreenUpdate::CrosstermEvent(Event::Key(KeyEvent { 
                     code: KeyCode::Backspace, kind: KeyEventKind::Press, .. 
                 })) => {
-                    if state.input_buffer.len() > 0 {
+                    if !state.input_buffer.is_empty() {
                         state.input_buffer.pop();
                     }
                 },
                 ScreenUpdate::CrosstermEvent(_) => continue,
                 ScreenUpdate::RootScore(s) => state.root_score = s,
                 ScreenUpdate::NextMove(m) => state.next_move = m,
+                ScreenUpdate::SearchDepth(d) => state.search_depth = d,
+                ScreenUpdate::Solution(s) => state.solution = s,
+                ScreenUpdate::Mcts(stats) => state.mcts = stats,
+                ScreenUpdate::MoveScores(scores) => state.move_scores = scores,
+                ScreenUpdate::Lines(lines) => state.lines = lines,
+                ScreenUpdate::Pondering(m) => state.pondering = m,
             }
 
             draw(&mut terminal, &mut state);
@@ -165,7 +344,7 @@ fn spawn_tui_thread(receiver: mpsc::Receiver<ScreenUpdate>, input_sender: mpsc::
 }
 
 fn spawn_listener_thread(sender: mpsc::Sender<ScreenUpdate>) -> thread::JoinHandle<()> {
-    return thread::spawn(move || {
+    thread::spawn(move || {
         loop {
             let event = crossterm::event::read().expect("success");
             sender.send(ScreenUpdate::CrosstermEvent(event)).expect("message sends");
@@ -178,9 +357,16 @@ pub enum ScreenUpdate {
     UpdateBoard (Board),
     UpdateOutput (String),
     CrosstermEvent (Event),
-    AnalysisCount (i32),
+    AnalysisCount (u64),
+    Throughput (u64, usize),  // Nodes per second, and on how many threads
     RootScore (i32),
-    NextMove (i32),
+    NextMove (Option<Move>),
+    SearchDepth (i32),
+    Solution (Option<Solution>),
+    Mcts (Vec<MoveStats>),
+    MoveScores (Vec<(Move, MoveScore)>),
+    Lines (Vec<Line>),
+    Pondering (Option<Move>),  // The reply being assumed
 }
 
 #[derive(Clone)]
@@ -219,17 +405,45 @@ impl ScreenManager {
         self.input_receiver.lock().unwrap().recv().expect("received")
     }
 
-    pub fn update_analysis_count(&self, count: i32) {
+    pub fn update_analysis_count(&self, count: u64) {
         self.sender.send(ScreenUpdate::AnalysisCount(count)).expect("sent");
     }
 
+    pub fn update_throughput(&self, per_second: u64, threads: usize) {
+        self.sender.send(ScreenUpdate::Throughput(per_second, threads)).expect("sent");
+    }
+
     pub fn update_root_score(&self, score: i32) {
         self.sender.send(ScreenUpdate::RootScore(score)).expect("sent");
     }
 
-    pub fn update_recomended_move(&self, next_move: i32) {
+    pub fn update_recomended_move(&self, next_move: Option<Move>) {
         self.sender.send(ScreenUpdate::NextMove(next_move)).expect("sent");
     }
+
+    pub fn update_search_depth(&self, depth: i32) {
+        self.sender.send(ScreenUpdate::SearchDepth(depth)).expect("sent");
+    }
+
+    pub fn update_solution(&self, solution: Option<Solution>) {
+        self.sender.send(ScreenUpdate::Solution(solution)).expect("sent");
+    }
+
+    pub fn update_move_scores(&self, scores: Vec<(Move, MoveScore)>) {
+        self.sender.send(ScreenUpdate::MoveScores(scores)).expect("sent");
+    }
+
+    pub fn update_lines(&self, lines: Vec<Line>) {
+        self.sender.send(ScreenUpdate::Lines(lines)).expect("sent");
+    }
+
+    pub fn update_pondering(&self, expected: Option<Move>) {
+        self.sender.send(ScreenUpdate::Pondering(expected)).expect("sent");
+    }
+
+    pub fn update_mcts(&self, stats: Vec<MoveStats>) {
+        self.sender.send(ScreenUpdate::Mcts(stats)).expect("sent");
+    }
 }
 
 impl Drop for ScreenManager {
@@ -239,3 +453,26 @@ impl Drop for ScreenManager {
         // self.listener_thread.take().expect("thread").join().expect("cleanup succeeds");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scores_line_up() {
+        let board = Board::from_moves("44").unwrap();
+        let scores = vec![
+            (Move::Drop(0), MoveScore::Heuristic(-123)),
+            (Move::Drop(3), MoveScore::Solved(Solution { winner: Some(Player::Red), plies: 37, best_move: None })),
+            (Move::Drop(4), MoveScore::Solved(Solution { winner: Some(Player::Yellow), plies: 12, best_move: None })),
+            (Move::Drop(6), MoveScore::Solved(Solution { winner: None, plies: 40, best_move: None })),
+        ];
+
+        let row = move_scores_row(&board, &scores);
+        assert_eq!(row, " -12      W37L12    = ");
+
+        // Each score is centered under its column's label.
+        let labels = board.display().lines().last().unwrap().to_string();
+        assert_eq!(labels.find('4'), row.find("W37").map(|i| i + 1));
+    }
+}
@@ -0,0 +1,481 @@
+use std::fmt;
+
+use crate::{board::{Board, Bits, Geometry, Player, MAX_WIDTH, MAX_CONNECT, fold_key, shl, shr}, transposition::{Bound, Entry, TranspositionTable}};
+
+// A strong solver. It works on raw bitboards and scores positions the way Pascal Pons'
+// solver does: a win scores higher the fewer of your own stones it takes, so the score
+// encodes the distance to the end of the game.
+
+// Empty cells that would complete a line for `position`.
+fn winning_cells(geometry: &Geometry, position: Bits, mask: Bits) -> Bits {
+    let connect = geometry.connect() as usize;
+
+    // vertical, only ever from below
+    let mut r = (1..connect).fold(geometry.board_mask(), |r, i| r & shl(position, i as i32));
+
+    // above[i] has the cells with i of their own stones in a row further along the line,
+    // below[i] the same going back. A cell wins if the two add up to connect - 1.
+    let mut above = [0; MAX_CONNECT as usize];
+    let mut below = above;
+
+    for d in &geometry.directions()[1..] {  // horizontal and both diagonals
+        above[0] = Bits::MAX;
+        below[0] = Bits::MAX;
+        for i in 1..connect {
+            above[i] = above[i - 1] & shr(position, i as i32 * d);
+            below[i] = below[i - 1] & shl(position, i as i32 * d);
+        }
+
+        for i in 0..connect {
+            r |= above[i] & below[connect - 1 - i];
+        }
+    }
+
+    r & (geometry.board_mask() ^ mask)
+}
+
+// The player to move's stones, and all stones.
+#[derive(Clone, Copy)]
+struct Position {
+    geometry: &'static Geometry,
+    current: Bits,
+    mask: Bits,
+    moves: i32,
+}
+
+impl Position {
+    fn from_board(board: &Board, player: Player) -> Position {
+        Position {
+            geometry: board.geometry(),
+            current: board.pieces(player),
+            mask: board.pieces(player) | board.pieces(player.opponent()),
+            moves: board.pieces_played(),
+        }
+    }
+
+    fn cells(&self) -> i32 {
+        self.geometry.cells()
+    }
+
+    // Columns never carry into each other, so mirroring the key is the same as keying the
+    // mirrored position. Both share the smaller one.
+    fn key(&self) -> u64 {
+        let key = self.current + self.mask;
+        fold_key(key.min(self.geometry.mirror_bits(key)))
+    }
+
+    fn can_play(&self, col: i32) -> bool {
+        self.mask & self.geometry.top_mask(col) == 0
+    }
+
+    fn possible(&self) -> Bits {
+        (self.mask + self.geometry.bottom()) & self.geometry.board_mask()
+    }
+
+    fn play(&self, move_bit: Bits) -> Position {
+        Position {
+            current: self.current ^ self.mask,
+            mask: self.mask | move_bit,
+            moves: self.moves + 1,
+            ..*self
+        }
+    }
+
+    fn move_in(&self, col: i32) -> Bits {
+        (self.mask + self.geometry.bottom_mask(col)) & self.geometry.column_mask(col)
+    }
+
+    fn winning_cells(&self, position: Bits) -> Bits {
+        winning_cells(self.geometry, position, self.mask)
+    }
+
+    fn can_win_next(&self) -> bool {
+        self.winning_cells(self.current) & self.possible() != 0
+    }
+
+    fn is_winning_move(&self, col: i32) -> bool {
+        self.winning_cells(self.current) & self.move_in(col) != 0
+    }
+
+    // Moves that don't hand the opponent an immediate win. Zero if every move loses.
+    fn non_losing_moves(&self) -> Bits {
+        let mut possible = self.possible();
+        let opponent_wins = self.winning_cells(self.current ^ self.mask);
+        let forced = possible & opponent_wins;
+
+        if forced != 0 {
+            if forced & (forced - 1) != 0 {
+                return 0;  // Two threats, can't block both.
+            }
+            possible = forced;
+        }
+
+        // Don't play right under the opponent's winning cell.
+        possible & !(opponent_wins >> 1)
+    }
+
+    // Number of threats the player to move would have after playing there.
+    fn move_score(&self, move_bit: Bits) -> u32 {
+        winning_cells(self.geometry, self.current | move_bit, self.mask).count_ones()
+    }
+}
+
+// Exact result of a position under perfect play.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Solution {
+    pub winner: Option<Player>,  // None for a draw
+    pub plies: i32,  // Until the game ends
+    pub best_move: Option<i32>,  // None if the game is over
+}
+
+impl fmt::Display for Solution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.winner {
+            Some(player) if self.plies == 0 => write!(f, "{player:?} won"),
+            Some(player) if self.plies == 1 => write!(f, "{player:?} wins in 1 ply"),
+            Some(player) => write!(f, "{player:?} wins in {} plies", self.plies),
+            None => write!(f, "Draw"),
+        }
+    }
+}
+
+impl Solution {
+    // Converts a score for the player to move (in a position with `moves` stones) into
+    // who wins and how many plies it takes.
+    fn from_score(score: i32, position: &Position, player: Player, best_move: Option<i32>) -> Solution {
+        let (moves, cells) = (position.moves, position.cells());
+
+        if score > 0 {
+            // Score counts down by one for each extra stone the winner needs.
+            let stones = (cells + 1 - moves) / 2 - score + 1;
+            Solution { winner: Some(player), plies: 2 * stones - 1, best_move }
+        }
+        else if score < 0 {
+            let stones = (cells - moves) / 2 + score + 1;
+            Solution { winner: Some(player.opponent()), plies: 2 * stones, best_move }
+        }
+        else {
+            Solution { winner: None, plies: cells - moves, best_move }
+        }
+    }
+}
+
+// Board keys never use the top bit (see fold_key), so this keeps solver entries apart from
+// the heuristic search's when they share a table.
+const SOLVER_TAG: u64 = 1 << 63;
+
+pub struct Solver<'a> {
+    table: &'a TranspositionTable,
+    pub nodes: u64,
+    stopped: bool,
+}
+
+impl Solver<'_> {
+    pub fn new(table: &TranspositionTable) -> Solver<'_> {
+        Solver {
+            table,
+            nodes: 0,
+            stopped: false,
+        }
+    }
+
+    // Only upper bounds get stored, as in the original solver.
+    fn table_get(&self, position: &Position) -> Option<i32> {
+        self.table.get(position.key() | SOLVER_TAG)
+            .map(|entry| entry.score)
+    }
+
+    fn table_put(&mut self, position: &Position, upper_bound: i32) {
+        // A solve goes all the way to the end, so it's as deep as an entry gets.
+        let depth = position.cells() - position.moves;
+        self.table.put(Entry::new(position.key() | SOLVER_TAG, upper_bound, depth, Bound::Upper, -1, position.moves));
+    }
+
+    // Solves the board. `stop` is polled every so often with the node count, and the
+    // solve is abandoned (returning None) as soon as it says so.
+    pub fn solve(&mut self, board: &Board, stop: &mut dyn FnMut(u64) -> bool) -> Option<Solution> {
+        let Some(player) = board.next_to_move()
+            else {
+                return Some(Solution {
+                    winner: board.winner(),
+                    plies: 0,
+                    best_move: None,
+                })
+            };
+
+        self.stopped = false;
+
+        let position = Position::from_board(board, player);
+        let score = self.score(&position, stop)?;
+
+        // Find a move that keeps the score.
+        let mut best_move = None;
+        for &col in position.geometry.column_order() {
+            if !position.can_play(col) {
+                continue;
+            }
+
+            if position.can_win_next() {
+                if position.is_winning_move(col) {
+                    best_move = Some(col);
+                    break;
+                }
+                continue;  // Don't bother solving anything else.
+            }
+
+            if -self.score(&position.play(position.move_in(col)), stop)? == score {
+                best_move = Some(col);
+                break;
+            }
+        }
+
+        Some(Solution::from_score(score, &position, player, best_move))
+    }
+
+    // Narrows down the exact score with null window searches.
+    fn score(&mut self, position: &Position, stop: &mut dyn FnMut(u64) -> bool) -> Option<i32> {
+        if position.can_win_next() {
+            return Some((position.cells() + 1 - position.moves) / 2);
+        }
+
+        let mut min = -(position.cells() - position.moves) / 2;
+        let mut max = (position.cells() + 1 - position.moves) / 2;
+
+        while min < max {
+            let mut med = min + (max - min) / 2;
+
+            // Probe near zero first, most positions are close to a draw.
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            }
+            else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+
+            let r = self.negamax(position, med, med + 1, stop);
+
+            if self.stopped {
+                return None;
+            }
+
+            if r <= med {
+                max = r;
+            }
+            else {
+                min = r;
+            }
+        }
+
+        Some(min)
+    }
+
+    // Assumes the player to move can't win right away.
+    fn negamax(&mut self, position: &Position, mut alpha: i32, mut beta: i32, stop: &mut dyn FnMut(u64) -> bool) -> i32 {
+        self.nodes += 1;
+
+        if self.nodes.is_multiple_of(4096) && stop(self.nodes) {
+            self.stopped = true;
+        }
+
+        if self.stopped {
+            return 0;  // Thrown away anyways
+        }
+
+        let next = position.non_losing_moves();
+        if next == 0 {
+            return -(position.cells() - position.moves) / 2;
+        }
+
+        if position.moves >= position.cells() - 2 {
+            return 0;
+        }
+
+        let min = -(position.cells() - 2 - position.moves) / 2;
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        let mut max = (position.cells() - 1 - position.moves) / 2;
+        if let Some(bound) = self.table_get(position) {
+            max = bound;
+        }
+
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        // Try the moves that make the most threats first. Stable insertion sort keeps
+        // the center first ordering for ties.
+        let mut moves: [(Bits, u32); MAX_WIDTH as usize] = [(0, 0); MAX_WIDTH as usize];
+        let mut count = 0;
+        for col in position.geometry.column_order().iter().rev() {
+            let move_bit = next & position.geometry.column_mask(*col);
+            if move_bit == 0 {
+                continue;
+            }
+
+            let score = position.move_score(move_bit);
+            let mut i = count;
+            while i > 0 && moves[i - 1].1 > score {
+                moves[i] = moves[i - 1];
+                i -= 1;
+            }
+            moves[i] = (move_bit, score);
+            count += 1;
+        }
+
+        for (move_bit, _) in moves[..count].iter().rev() {
+            let score = -self.negamax(&position.play(*move_bit), -beta, -alpha, stop);
+
+            if score >= beta {
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+
+        self.table_put(position, alpha);
+        alpha
+    }
+}
+
+#[allow(unused)]
+pub fn solve(board: &Board) -> Solution {
+    let table = TranspositionTable::with_memory(16);
+    Solver::new(&table).solve(board, &mut |_| false).expect("never stopped")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::Dimensions;
+    use crate::rules::Standard;
+
+    fn play_moves(moves: &[i32]) -> Board {
+        let mut board = Board::new();
+        for col in moves {
+            let player = board.next_to_move().unwrap();
+            board = board.play(*col, player, true).expect("legal");
+        }
+        board
+    }
+
+    // Plain minimax, preferring quick wins and slow losses.
+    fn brute_force(board: &Board) -> (Option<Player>, i32) {
+        let Some(player) = board.next_to_move()
+            else { return (board.winner(), 0) };
+
+        let mut best: Option<(i32, Option<Player>, i32)> = None;
+        for child in board.next_boards() {
+            let (winner, plies) = brute_force(&child);
+            let rank = match winner {
+                Some(p) if p == player => 1000 - plies,
+                Some(_) => -1000 + plies,
+                None => 0,
+            };
+
+            if best.is_none_or(|(r, _, _)| rank > r) {
+                best = Some((rank, winner, plies + 1));
+            }
+        }
+
+        let (_, winner, plies) = best.unwrap();
+        (winner, plies)
+    }
+
+    #[test]
+    fn immediate_win() {
+        let board = play_moves(&[0, 1, 0, 1, 0, 1]);
+        let solution = solve(&board);
+
+        assert_eq!(solution, Solution { winner: Some(Player::Red), plies: 1, best_move: Some(0) });
+        assert_eq!(solution.to_string(), "Red wins in 1 ply");
+        assert_eq!(Solution { plies: 3, ..solution }.to_string(), "Red wins in 3 plies");
+    }
+
+    #[test]
+    fn double_threat() {
+        let board = play_moves(&[1, 5, 2, 5]);
+        let solution = solve(&board);
+
+        assert_eq!(solution, Solution { winner: Some(Player::Red), plies: 3, best_move: Some(3) });
+    }
+
+    #[test]
+    fn mirrored_positions_agree() {
+        let table = TranspositionTable::with_memory(16);
+        let mut solver = Solver::new(&table);
+
+        let board = play_moves(&[3, 3, 3, 3, 2, 4, 0, 1, 2, 2, 4, 4, 1, 0, 5, 0, 0, 6, 6, 6, 5]);
+        let solution = solver.solve(&board, &mut |_| false).unwrap();
+        let mirrored = solver.solve(&board.mirrored(), &mut |_| false).unwrap();
+
+        assert_eq!((solution.winner, solution.plies), (mirrored.winner, mirrored.plies));
+
+        // The mirrored best move has to hold the result too, though it may not be the one
+        // picked first.
+        let col = board.geometry().mirror_col(solution.best_move.unwrap());
+        let child = board.mirrored().play(col, board.next_to_move().unwrap(), true).unwrap();
+        let after = solver.solve(&child, &mut |_| false).unwrap();
+        assert_eq!((after.winner, after.plies), (solution.winner, solution.plies - 1));
+    }
+
+    #[test]
+    fn game_over() {
+        let board = play_moves(&[0, 1, 0, 1, 0, 1, 0]);
+        assert_eq!(solve(&board).to_string(), "Red won");
+    }
+
+    // Random games that stop with ~10 cells left, solved both ways.
+    fn check_against_brute_force(dimensions: Dimensions, games: i32) {
+        let mut seed: u64 = 987654321;
+        let table = TranspositionTable::with_memory(16);
+        let mut solver = Solver::new(&table);
+        let mut checked = 0;
+
+        while checked < games {
+            let mut board = Board::empty(dimensions, &Standard);
+            while board.pieces_played() < dimensions.cells() - 10 && board.next_to_move().is_some() {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let col = ((seed >> 33) % dimensions.width as u64) as i32;
+                if let Ok(next) = board.play(col, board.next_to_move().unwrap(), true) {
+                    board = next;
+                }
+            }
+
+            if board.next_to_move().is_none() {
+                continue;
+            }
+
+            let solution = solver.solve(&board, &mut |_| false).unwrap();
+            assert_eq!((solution.winner, solution.plies), brute_force(&board), "{}", board.display());
+
+            // The suggested move has to actually keep the result.
+            let child = board.play(solution.best_move.unwrap(), board.next_to_move().unwrap(), true).unwrap();
+            let after = solver.solve(&child, &mut |_| false).unwrap();
+            assert_eq!(after.winner, solution.winner);
+            assert_eq!(after.plies, solution.plies - 1);
+
+            checked += 1;
+        }
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        check_against_brute_force(Dimensions::STANDARD, 40);
+    }
+
+    #[test]
+    fn other_dimensions() {
+        check_against_brute_force(Dimensions::new(9, 7, 5).unwrap(), 20);
+        check_against_brute_force(Dimensions::new(6, 5, 3).unwrap(), 20);
+        check_against_brute_force(Dimensions::new(4, 9, 4).unwrap(), 20);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::board::{Bits, Board, Player};
+
+// Threats: empty cells that would finish a line for someone. Which rows they're on decides
+// a lot of games. Red moves first, so once the board fills up Red gets to claim the odd
+// rows (counting from 1 at the bottom) and Yellow the even ones. A threat on your own
+// parity tends to come good in the end, one on the other's rarely does.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Threat {
+    pub player: Player,
+    pub row: i32,  // 0 based, like everything else. Row 0 is row 1, which is odd.
+    pub col: i32,
+    pub playable: bool,  // Could be played into right now, rather than some time later
+}
+
+impl Threat {
+    pub fn odd(&self) -> bool {
+        self.row % 2 == 0
+    }
+}
+
+// Empty cells that would finish a line for `player`, as a bitboard.
+pub fn threat_cells(board: &Board, player: Player) -> Bits {
+    let yours = board.pieces(player);
+    let enemies = board.pieces(player.opponent());
+    let connect = board.dimensions().connect as u32;
+
+    board.geometry().windows().iter()
+        .filter(|window| *window & enemies == 0 && (*window & yours).count_ones() == connect - 1)
+        .fold(0, |cells, window| cells | (window & !yours))
+}
+
+// Every threat `player` has, left to right and bottom up.
+pub fn threats(board: &Board, player: Player) -> Vec<Threat> {
+    let geometry = board.geometry();
+    let cells = threat_cells(board, player);
+    let playable = board.playable();
+
+    let mut threats = vec![];
+    for col in 0..geometry.width() {
+        for row in 0..geometry.height() {
+            let cell = geometry.bottom_mask(col) << row;
+            if cells & cell != 0 {
+                threats.push(Threat { player, row, col, playable: playable & cell != 0 });
+            }
+        }
+    }
+
+    threats
+}
+
+// Rows counting from 1 at the bottom, the odd ones.
+pub fn odd_rows(board: &Board) -> Bits {
+    let geometry = board.geometry();
+    (0..geometry.height()).step_by(2)
+        .fold(0, |rows, row| rows | geometry.row_mask(row))
+}
+
+// Who should win once the board fills up, going by threats and parity alone. A rule of
+// thumb rather than a proof, and None when it doesn't say.
+//
+// Only threats on your own parity count, and in each column only the lowest threat, since
+// whoever's is lower gets it first. If just one player is left with any, they win. It
+// leans on Yellow being able to answer every move in the same column, so it only holds for
+// columns of even height and rules without pops.
+pub fn zugzwang(board: &Board) -> Option<Player> {
+    let geometry = board.geometry();
+    if board.plies_left().is_none() || geometry.height() % 2 != 0 {
+        return None;
+    }
+
+    let odd = odd_rows(board);
+    let red = threat_cells(board, Player::Red) & odd;
+    let yellow = threat_cells(board, Player::Yellow) & !odd & geometry.board_mask();
+
+    let (mut red_live, mut yellow_live) = (false, false);
+    for col in 0..geometry.width() {
+        let column = geometry.column_mask(col);
+        let (red, yellow) = (red & column, yellow & column);
+
+        // Lower bits are lower rows.
+        match (red, yellow) {
+            (0, 0) => (),
+            (_, 0) => red_live = true,
+            (0, _) => yellow_live = true,
+            _ if red.trailing_zeros() < yellow.trailing_zeros() => red_live = true,
+            _ => yellow_live = true,
+        }
+    }
+
+    match (red_live, yellow_live) {
+        (true, false) => Some(Player::Red),
+        (false, true) => Some(Player::Yellow),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{board::Dimensions, rules::{PopOut, Standard}};
+
+    #[test]
+    fn finds_threats() {
+        // Red has 1, 2 and 3 on the bottom row, so column 4 would finish it. Yellow's
+        // three stacked in column 7 are short one on top.
+        let board = Board::from_moves("172737").unwrap();
+        let geometry = board.geometry();
+        assert_eq!(threat_cells(&board, Player::Red), geometry.bottom_mask(3));
+        assert_eq!(threat_cells(&board, Player::Yellow), geometry.bottom_mask(6) << 3);
+
+        let red = Threat { player: Player::Red, row: 0, col: 3, playable: true };
+        let yellow = Threat { player: Player::Yellow, row: 3, col: 6, playable: true };
+        assert_eq!(threats(&board, Player::Red), vec![red]);
+        assert_eq!(threats(&board, Player::Yellow), vec![yellow]);
+        assert!(red.odd());
+        assert!(!yellow.odd());
+    }
+
+    #[test]
+    fn zugzwang() {
+        // Red has three on row 3 with nothing under the fourth cell yet, and Yellow has
+        // no threats at all.
+        let board = Board::from_moves("211243231737").unwrap();
+        assert_eq!(threats(&board, Player::Red), vec![Threat { player: Player::Red, row: 2, col: 3, playable: false }]);
+        assert_eq!(threats(&board, Player::Yellow), vec![]);
+        assert_eq!(super::zugzwang(&board), Some(Player::Red));
+
+        // Yellow gets an even threat under it in the same column, and so the column.
+        let blocked = board.play(4, Player::Red, true).unwrap()
+            .play(4, Player::Yellow, true).unwrap();
+        assert!(threats(&blocked, Player::Yellow).contains(&Threat { player: Player::Yellow, row: 1, col: 3, playable: true }), "{}", blocked.display());
+        assert_eq!(super::zugzwang(&blocked), Some(Player::Yellow));
+
+        assert_eq!(super::zugzwang(&Board::new()), None);
+        assert_eq!(super::zugzwang(&Board::empty(Dimensions::new(7, 5, 4).unwrap(), &Standard)), None);
+        assert_eq!(super::zugzwang(&Board::empty(Dimensions::STANDARD, &PopOut)), None);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{mem::size_of, sync::atomic::{AtomicU8, AtomicU64, Ordering::Relaxed}};
+
+// Fixed size transposition table shared by the searches. Buckets hold two entries: the
+// first keeps the deepest result seen (until a new search comes along), the second is
+// always overwritten, so recent positions still get cached when the first is taken.
+//
+// Search threads read and write it all at once without locking. Each entry is packed
+// into a u64 and stored alongside its key xor'd with it, so one caught half written
+// comes out with the wrong key and just looks like a miss.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bound {
+    Exact,
+    Lower,  // Real score is at least this
+    Upper,  // Real score is at most this
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub score: i32,
+    pub depth: i8,
+    pub bound: Bound,
+    pub best_move: i8,  // -1 if unknown
+    pub pieces: u8,  // On the board, for telling which positions can't come up again
+    generation: u8,
+}
+
+impl Entry {
+    pub fn new(key: u64, score: i32, depth: i32, bound: Bound, best_move: i32, pieces: i32) -> Entry {
+        Entry {
+            key,
+            score,
+            depth: depth as i8,
+            bound,
+            best_move: best_move as i8,
+            pieces: pieces as u8,
+            generation: 0,
+        }
+    }
+}
+
+impl Entry {
+    // Depth and pieces get 7 bits each, which is plenty for the biggest board. Bound is
+    // never 0, so neither is a packed entry. An empty slot is all zeros.
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+
+        self.score as u32 as u64
+            | (self.depth as u64 & 0x7f) << 32
+            | (self.best_move as u8 as u64) << 39
+            | (self.generation as u64) << 47
+            | bound << 55
+            | (self.pieces as u64 & 0x7f) << 57
+    }
+
+    fn unpack(key: u64, data: u64) -> Entry {
+        let bound = match (data >> 55) & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+
+        Entry {
+            key,
+            score: data as u32 as i32,
+            depth: ((data >> 32) & 0x7f) as i8,
+            bound,
+            best_move: (data >> 39) as u8 as i8,
+            pieces: (data >> 57) as u8,
+            generation: (data >> 47) as u8,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    check: AtomicU64,  // Key xor data
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> Option<Entry> {
+        let data = self.data.load(Relaxed);
+        let key = self.check.load(Relaxed) ^ data;
+        (data != 0).then(|| Entry::unpack(key, data))
+    }
+
+    fn store(&self, entry: Entry) {
+        let data = entry.pack();
+        self.data.store(data, Relaxed);
+        self.check.store(entry.key ^ data, Relaxed);
+    }
+
+    fn empty(&self) {
+        self.data.store(0, Relaxed);
+        self.check.store(0, Relaxed);
+    }
+}
+
+type Bucket = [Slot; 2];
+
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn with_memory(megabytes: usize) -> TranspositionTable {
+        let count = (megabytes * 1024 * 1024 / size_of::<Bucket>()).max(1);
+
+        TranspositionTable {
+            buckets: (0..count).map(|_| Bucket::default()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    fn bucket(&self, key: u64) -> usize {
+        // Keys are bitboards, so the low bits are far from random. Mix them first.
+        (key.wrapping_mul(0x9E3779B97F4A7C15) >> 16) as usize % self.buckets.len()
+    }
+
+    // Marks everything currently stored as old, so it gets replaced before newer results.
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Relaxed);
+    }
+
+    // Forgets everything, for when the scores stored no longer mean the same thing.
+    pub fn clear(&self) {
+        self.buckets.iter().flatten().for_each(Slot::empty);
+    }
+
+    // Empties every slot whose entry fails `keep`, returning how many that was. For
+    // throwing out positions that can't be reached anymore, so they stop taking up room.
+    pub fn retain(&self, keep: impl Fn(&Entry) -> bool) -> usize {
+        let mut freed = 0;
+
+        for slot in self.buckets.iter().flatten() {
+            if slot.load().is_some_and(|entry| !keep(&entry)) {
+                slot.empty();
+                freed += 1;
+            }
+        }
+
+        freed
+    }
+
+    pub fn get(&self, key: u64) -> Option<Entry> {
+        self.buckets[self.bucket(key)].iter()
+            .filter_map(Slot::load)
+            .find(|entry| entry.key == key)
+    }
+
+    // Another thread can get in between the look and the store. The worst that does is
+    // lose an entry, which a cache can live with.
+    pub fn put(&self, mut entry: Entry) {
+        let generation = self.generation.load(Relaxed);
+        entry.generation = generation;
+
+        let bucket = &self.buckets[self.bucket(entry.key)];
+
+        let replace_deep = match bucket[0].load() {
+            None => true,
+            Some(old) => old.key == entry.key || old.generation != generation || entry.depth >= old.depth,
+        };
+
+        if replace_deep {
+            // Bump the old deep entry down instead of losing it.
+            if let Some(old) = bucket[0].load() {
+                if old.key != entry.key {
+                    bucket[1].store(old);
+                }
+            }
+            bucket[0].store(entry);
+        }
+        else {
+            bucket[1].store(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn store_and_replace() {
+        let table = TranspositionTable::with_memory(1);
+
+        table.put(Entry::new(42, 10, 5, Bound::Exact, 3, 0));
+        assert_eq!(table.get(42).map(|e| e.score), Some(10));
+        assert_eq!(table.get(43), None);
+
+        // Shallower result for the same key still replaces it.
+        table.put(Entry::new(42, 7, 2, Bound::Lower, 1, 0));
+        let entry = table.get(42).unwrap();
+        assert_eq!((entry.score, entry.depth, entry.bound, entry.best_move), (7, 2, Bound::Lower, 1));
+    }
+
+    #[test]
+    fn bounded_size() {
+        let table = TranspositionTable::with_memory(1);
+        let buckets = table.buckets.len();
+
+        for key in 0..(10 * buckets as u64) {
+            table.put(Entry::new(key, 0, 1, Bound::Exact, -1, 0));
+        }
+
+        assert_eq!(table.buckets.len(), buckets);
+        assert!(table.buckets.iter().flatten().all(|slot| slot.load().is_some()));
+    }
+
+    #[test]
+    fn deep_entries_survive() {
+        let table = TranspositionTable::with_memory(1);
+        let buckets = table.buckets.len() as u64;
+
+        table.put(Entry::new(1, 0, 20, Bound::Exact, -1, 0));
+
+        // Find keys landing in the same bucket and hammer it with shallow results.
+        let i = table.bucket(1);
+        let colliding: Vec<u64> = (2..(100 * buckets)).filter(|k| table.bucket(*k) == i).take(10).collect();
+
+        for key in &colliding {
+            table.put(Entry::new(*key, 0, 1, Bound::Exact, -1, 0));
+        }
+        assert!(table.get(1).is_some());
+
+        // Unless the search has moved on.
+        table.new_search();
+        for key in colliding {
+            table.put(Entry::new(key, 0, 1, Bound::Exact, -1, 0));
+        }
+        assert!(table.get(1).is_none());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let entry = Entry::new(7, -123_456, 108, Bound::Upper, -1, 108);
+        assert_eq!(Entry::unpack(7, entry.pack()), entry);
+
+        // Threads writing over each other never leave a mangled entry behind, since every
+        // score here is its key's.
+        let table = TranspositionTable::with_memory(1);
+        std::thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let table = &table;
+                scope.spawn(move || {
+                    for i in 0..50_000u64 {
+                        let key = (i * 4 + thread) % 20_000;
+                        table.put(Entry::new(key, key as i32, (i % 30) as i32, Bound::Exact, (i % 7) as i32, 0));
+                        if let Some(found) = table.get(key ^ 1) {
+                            assert_eq!(found.score as u64, found.key);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn retain() {
+        let table = TranspositionTable::with_memory(1);
+        for key in 0..100 {
+            table.put(Entry::new(key, 0, 1, Bound::Exact, -1, key as i32 % 10));
+        }
+
+        assert_eq!(table.retain(|entry| entry.pieces >= 4), 40);
+        assert_eq!(table.get(13), None);
+        assert_eq!(table.get(14).map(|entry| entry.pieces), Some(4));
+    }
+}
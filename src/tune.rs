@@ -0,0 +1,321 @@
+use std::fs;
+
+use crate::{
+    analysis::fixed_depth_move,
+    board::{Board, Dimensions, Move, Player},
+    engine::{Controller, parse_depth},
+    eval::{self, Evaluator},
+    game::{Game, Outcome},
+    options::{parse_size, value},
+    random::Rng,
+    rules::{self, Rules},
+    transposition::TranspositionTable,
+    weights::Weights,
+};
+
+// Tuning evaluator weights by self-play, with SPSA. Each iteration nudges every weight up or
+// down at random, plays the nudged-up weights against the nudged-down ones, and moves the
+// weights towards whichever side won. Slow, but it needs nothing but games. The games are
+// played by the analysis' own search, so the weights get tuned for the engine that uses them.
+
+// Each side's table, per game. The searches are shallow, this is plenty.
+const TABLE_MEGABYTES: usize = 1;
+
+pub const USAGE: &str = "\
+Usage: connect_four tune [options]
+
+Options:
+    --eval <name>              Evaluator to tune: default, threats, parity, or center (default default)
+    --weights <file>           Weights to start from (default built in)
+    --out <file>               Where to write the tuned weights (default tuned.toml)
+    --iterations <n>           Rounds of nudging (default 100)
+    --games <n>                Games per round, each played with both colors (default 8)
+    --depth <n>                Search depth the engines play at, 1 to 10 (default 2)
+    --opening <n>              Random moves to start each game, for variety (default 4)
+    --seed <n>                 Seed for the openings and nudges (default from the clock)
+    --size <width>x<height>    Board size (default 7x6)
+    --connect <n>              How many in a row it takes to win (default 4)
+    --variant <rules>          standard, popout, or cylinder (default standard)
+    --help                     Show this message";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuning {
+    pub evaluator: &'static dyn Evaluator,
+    pub out: String,
+    pub iterations: usize,
+    pub games: usize,
+    pub depth: i32,
+    pub opening: usize,
+    pub seed: Option<u64>,
+    pub dimensions: Dimensions,
+    pub rules: &'static dyn Rules,
+}
+
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning {
+            evaluator: eval::DEFAULT,
+            out: "tuned.toml".into(),
+            iterations: 100,
+            games: 8,
+            depth: 2,
+            opening: 4,
+            seed: None,
+            dimensions: Dimensions::STANDARD,
+            rules: &rules::Standard,
+        }
+    }
+}
+
+fn parse_count(flag: &str, count: &str) -> Result<usize, String> {
+    match count.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Bad count for {flag}: {count}")),
+    }
+}
+
+// Args are the ones after "tune". Err holds a message for the user.
+pub fn parse<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<Tuning, String> {
+    let mut tuning = Tuning::default();
+    let mut args = args.into_iter();
+
+    // Both of these wait until the end, since the weights go with the evaluator.
+    let mut evaluator = eval::DEFAULT;
+    let mut weights = Weights::DEFAULT;
+
+    let Dimensions { mut width, mut height, mut connect } = tuning.dimensions;
+
+    while let Some(arg) = args.next() {
+        match arg {
+            "--eval" => evaluator = eval::by_name(value(arg, &mut args)?)?,
+            "--weights" => weights = Weights::load(value(arg, &mut args)?)?,
+            "--out" => tuning.out = value(arg, &mut args)?.to_string(),
+            "--iterations" => tuning.iterations = parse_count(arg, value(arg, &mut args)?)?,
+            "--games" => tuning.games = parse_count(arg, value(arg, &mut args)?)?,
+            "--depth" => tuning.depth = parse_depth(value(arg, &mut args)?)?,
+            "--opening" => {
+                let plies = value(arg, &mut args)?;
+                tuning.opening = plies.parse().map_err(|_| format!("Bad opening length: {plies}"))?;
+            }
+            "--seed" => {
+                let seed = value(arg, &mut args)?;
+                tuning.seed = Some(seed.parse().map_err(|_| format!("Bad seed: {seed}"))?);
+            }
+            "--size" => (width, height) = parse_size(value(arg, &mut args)?)?,
+            "--connect" => {
+                let n = value(arg, &mut args)?;
+                connect = n.parse().map_err(|_| format!("Bad connect length: {n}"))?;
+            }
+            "--variant" => tuning.rules = rules::by_name(value(arg, &mut args)?)?,
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ => return Err(format!("Unknown option: {arg}\n\n{USAGE}")),
+        }
+    }
+
+    tuning.dimensions = Dimensions::new(width, height, connect)?;
+    tuning.evaluator = eval::with_weights(evaluator, weights);
+
+    Ok(tuning)
+}
+
+// Random legal moves to start from. May be shorter if the game ends.
+fn random_opening(start: &Board, plies: usize, rng: &mut Rng) -> Vec<Move> {
+    let mut game = Game::new(start.clone(), Controller::Human, Controller::Human);
+
+    for _ in 0..plies {
+        let legal = game.board().legal_moves();
+        if game.to_move().is_none() || legal.is_empty() {
+            break;
+        }
+        game.play(legal[rng.below(legal.len())]).expect("legal");
+    }
+
+    game.moves().to_vec()
+}
+
+// Plays one game out from the opening. Games that drag on (PopOut can) are called a draw.
+fn play_game(start: &Board, opening: &[Move], red: &dyn Evaluator, yellow: &dyn Evaluator, depth: i32) -> Outcome {
+    let mut game = Game::new(start.clone(), Controller::Human, Controller::Human);
+    for m in opening {
+        game.play(*m).expect("opening is legal");
+    }
+
+    let max_plies = 4 * start.dimensions().cells() as usize;
+
+    // One each, since scores from one evaluator mean nothing to the other.
+    let red_table = TranspositionTable::with_memory(TABLE_MEGABYTES);
+    let yellow_table = TranspositionTable::with_memory(TABLE_MEGABYTES);
+
+    while let Some(player) = game.to_move() {
+        if game.ply() >= max_plies {
+            return Outcome::Draw;
+        }
+
+        let (evaluator, table) = match player {
+            Player::Red => (red, &red_table),
+            Player::Yellow => (yellow, &yellow_table),
+        };
+        let m = fixed_depth_move(game.board(), evaluator, depth, table, None).expect("game is not over");
+        game.play(m).expect("engine plays legal moves");
+    }
+
+    game.outcome()
+}
+
+// Points for `a` out of every game played, wins 1 and draws a half. Each opening is played
+// twice, with the colors swapped, so neither side gets the better half of them.
+fn play_match(tuning: &Tuning, a: &dyn Evaluator, b: &dyn Evaluator, rng: &mut Rng) -> f64 {
+    let start = Board::empty(tuning.dimensions, tuning.rules);
+    let mut points = 0.0;
+
+    for _ in 0..tuning.games {
+        let opening = random_opening(&start, tuning.opening, rng);
+
+        for a_plays in [Player::Red, Player::Yellow] {
+            let (red, yellow) = if a_plays == Player::Red { (a, b) } else { (b, a) };
+
+            points += match play_game(&start, &opening, red, yellow, tuning.depth) {
+                Outcome::Win(player) if player == a_plays => 1.0,
+                Outcome::Win(_) => 0.0,
+                _ => 0.5,
+            };
+        }
+    }
+
+    points / (2 * tuning.games) as f64
+}
+
+// Held inside what Weights::parse takes, so every file written loads again.
+fn with_values(weights: Weights, names: &[&str], values: &[f64]) -> Result<Weights, String> {
+    let mut weights = weights;
+    let limit = weights.heuristic_limit() as f64;
+    for (name, value) in names.iter().zip(values) {
+        *weights.field_mut(name).expect("evaluators only name real weights") = value.round().clamp(-limit, limit) as i32;
+    }
+
+    weights.validate()?;
+    Ok(weights)
+}
+
+fn save(path: &str, weights: &Weights, note: &str) -> Result<(), String> {
+    fs::write(path, format!("# {note}\n{weights}"))
+        .map_err(|err| format!("Can't write {path}: {err}"))
+}
+
+// Runs the whole thing, reporting along the way. Writes the weights after every iteration,
+// so stopping early still leaves something behind.
+pub fn run(tuning: &Tuning, report: &mut impl FnMut(String)) -> Result<Weights, String> {
+    let seed = tuning.seed.unwrap_or_else(Rng::seed_from_time);
+    let mut rng = Rng::new(seed);
+
+    let original = tuning.evaluator;
+    let names = original.tunable();
+    let start: Vec<f64> = names.iter().map(|name| original.weights().get(name).unwrap() as f64).collect();
+
+    // Steps are relative to each weight's size, so 5 and 500 both get to move. Small ones
+    // still get room to move at all.
+    let scale: Vec<f64> = start.iter().map(|value| value.abs().max(10.0)).collect();
+    let mut theta = start.clone();
+    let limit = original.weights().heuristic_limit() as f64;
+
+    report(format!("Tuning {} ({}) for {} iterations of {} games. Seed {seed}.",
+        original.name(), names.join(", "), tuning.iterations, 2 * tuning.games));
+
+    for k in 0..tuning.iterations {
+        // The usual SPSA decay: nudges shrink slowly, steps a little faster.
+        let c = 0.1 / ((k + 1) as f64).powf(0.101);
+        let a = 0.5 / ((k + 1) as f64).powf(0.602);
+
+        let delta: Vec<f64> = names.iter().map(|_| if rng.chance(0.5) { 1.0 } else { -1.0 }).collect();
+        let nudged = |sign: f64| -> Vec<f64> {
+            theta.iter().zip(&delta).zip(&scale).map(|((t, d), s)| t + sign * c * s * d).collect()
+        };
+
+        // Kept local, there's a new pair every iteration.
+        let plus = original.with_weights(with_values(*original.weights(), names, &nudged(1.0))?);
+        let minus = original.with_weights(with_values(*original.weights(), names, &nudged(-1.0))?);
+
+        // Positive when the nudged up side won. Moves every weight along its own nudge.
+        let result = 2.0 * play_match(tuning, plus.as_ref(), minus.as_ref(), &mut rng) - 1.0;
+        for ((t, d), s) in theta.iter_mut().zip(&delta).zip(&scale) {
+            *t = (*t + a * s * result * d).clamp(-limit, limit);
+        }
+
+        let weights = with_values(*original.weights(), names, &theta)?;
+        save(&tuning.out, &weights, &format!("Tuned {} by self-play, {} of {} iterations, seed {seed}", original.name(), k + 1, tuning.iterations))?;
+
+        let values: Vec<String> = names.iter().map(|name| format!("{name} {}", weights.get(name).unwrap())).collect();
+        report(format!("Iteration {}: nudged up scored {:+.2}. {}", k + 1, result, values.join(", ")));
+    }
+
+    // Evidence, or the lack of it.
+    let tuned = original.with_weights(with_values(*original.weights(), names, &theta)?);
+    let score = play_match(tuning, tuned.as_ref(), original, &mut rng);
+    report(format!("Tuned weights scored {:.1}% against the originals. Written to {}.", 100.0 * score, tuning.out));
+
+    Ok(*tuned.weights())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_tuning() {
+        assert_eq!(parse([]), Ok(Tuning::default()));
+
+        let tuning = parse(["--eval", "parity", "--games", "3", "--size", "5x4", "--out", "x.toml"]).unwrap();
+        assert_eq!(tuning.evaluator.name(), "parity");
+        assert_eq!((tuning.games, tuning.dimensions.width, tuning.out.as_str()), (3, 5, "x.toml"));
+
+        assert!(parse(["--games", "0"]).is_err());
+        assert!(parse(["--eval", "magic"]).is_err());
+        assert!(parse(["--red", "human"]).is_err());
+    }
+
+    #[test]
+    fn takes_the_win() {
+        let table = TranspositionTable::with_memory(TABLE_MEGABYTES);
+        let board = Board::from_moves("121212").unwrap();
+        for depth in 1..4 {
+            assert_eq!(fixed_depth_move(&board, eval::DEFAULT, depth, &table, None), Some(Move::Drop(0)));
+        }
+
+        // And blocks it, when it's the other side's.
+        let board = Board::from_moves("121374").unwrap();
+        assert_eq!(fixed_depth_move(&board, eval::DEFAULT, 2, &table, None), Some(Move::Drop(4)));
+
+        // A weighted copy that never went through the registry plays too.
+        let local = eval::DEFAULT.with_weights(Weights { three: 900, ..Weights::DEFAULT });
+        let table = TranspositionTable::with_memory(TABLE_MEGABYTES);
+        assert_eq!(fixed_depth_move(&board, local.as_ref(), 3, &table, None), Some(Move::Drop(4)));
+    }
+
+    #[test]
+    fn tunes_and_writes() {
+        let out = std::env::temp_dir().join(format!("connect_four_tune_{}.toml", std::process::id()));
+        let tuning = Tuning {
+            out: out.to_string_lossy().into_owned(),
+            iterations: 2,
+            games: 1,
+            depth: 1,
+            seed: Some(3),
+            dimensions: Dimensions::new(5, 4, 3).unwrap(),
+            ..Tuning::default()
+        };
+
+        let mut lines = vec![];
+        let weights = run(&tuning, &mut |line| lines.push(line)).unwrap();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(Weights::load(&tuning.out), Ok(weights));
+        assert_eq!(weights.center, Weights::DEFAULT.center);  // Not the default evaluator's
+        fs::remove_file(out).unwrap();
+
+        // Nothing gets out of bounds, however hard it's pushed.
+        let small = Weights { win: 300_000, ..Weights::DEFAULT };
+        let pushed = with_values(small, &["three", "two"], &[5000.0, -1e9]).unwrap();
+        assert_eq!((pushed.three, pushed.two), (3000, -3000));
+        assert!(with_values(Weights { zugzwang: 5000, ..small }, &["three"], &[500.0]).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fmt, fs};
+
+// The numbers the evaluators go by. Kept in files so they can be played with (and tuned)
+// without recompiling. A file is lines of `name = value`, with # for comments. Anything
+// left out keeps its default, see weights/default.toml.
+
+// Wins can't score more than this, or the searches' infinity wouldn't be.
+pub const MAX_WIN: i32 = 1000000000;
+
+// How far a win has to be above every other weight, so no one window or threat comes close.
+// Plenty of them together still could, so Evaluator::score holds heuristics short of a win
+// as well, where the searches would take them for a forced one.
+const WIN_MARGIN: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Weights {
+    pub win: i32,
+    // A window (possible line) holding that many of your pieces and none of the enemy's,
+    // on connect 4. Longer lines count how many they're short, so `three` is one short.
+    pub one: i32,
+    pub two: i32,
+    pub three: i32,
+    pub center: i32,  // Per piece, for each step closer to the middle column
+    pub threat: i32,  // An empty cell that would finish one of your lines
+    pub playable_threat: i32,  // On top of `threat`, if the cell can be played right away
+    pub parity_good: i32,  // A threat on your own rows, odd for Red and even for Yellow
+    pub parity_bad: i32,  // A threat on the other player's rows
+    pub zugzwang: i32,  // Threats say you win once the board fills up
+}
+
+impl Weights {
+    pub const DEFAULT: Weights = Weights {
+        win: MAX_WIN,
+        one: 5,
+        two: 50,
+        three: 500,
+        center: 10,
+        threat: 100,
+        playable_threat: 400,
+        parity_good: 300,
+        parity_bad: 50,
+        zugzwang: 2000,
+    };
+
+    // Every weight by name, in file order.
+    fn fields(&mut self) -> [(&'static str, &mut i32); 10] {
+        [
+            ("win", &mut self.win),
+            ("one", &mut self.one),
+            ("two", &mut self.two),
+            ("three", &mut self.three),
+            ("center", &mut self.center),
+            ("threat", &mut self.threat),
+            ("playable_threat", &mut self.playable_threat),
+            ("parity_good", &mut self.parity_good),
+            ("parity_bad", &mut self.parity_bad),
+            ("zugzwang", &mut self.zugzwang),
+        ]
+    }
+
+    pub fn get(&self, name: &str) -> Option<i32> {
+        let mut weights = *self;
+        weights.field_mut(name).map(|field| *field)
+    }
+
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut i32> {
+        self.fields().into_iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn parse(text: &str) -> Result<Weights, String> {
+        let mut weights = Weights::DEFAULT;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let Some((name, value)) = line.split_once('=')
+                else { return Err(format!("Line {}: expected name = value", i + 1)) };
+            let (name, value) = (name.trim(), value.trim());
+
+            let Some(field) = weights.field_mut(name)
+                else { return Err(format!("Line {}: unknown weight {name}", i + 1)) };
+
+            *field = value.parse().map_err(|_| format!("Line {}: bad value for {name}: {value}", i + 1))?;
+        }
+
+        weights.validate()?;
+        Ok(weights)
+    }
+
+    // What parse checks, for weights that came from somewhere else (like tuning).
+    pub fn validate(&self) -> Result<(), String> {
+        if self.win <= 0 || self.win > MAX_WIN {
+            return Err(format!("win has to be between 1 and {MAX_WIN}"));
+        }
+
+        let largest = self.largest_heuristic();
+        if largest > self.heuristic_limit() as u64 {
+            return Err(format!("win has to be at least {WIN_MARGIN} times every other weight ({})", WIN_MARGIN * largest));
+        }
+
+        Ok(())
+    }
+
+    // As big as any weight but `win` gets to be, either sign.
+    pub fn heuristic_limit(&self) -> i32 {
+        self.win / WIN_MARGIN as i32
+    }
+
+    // The biggest weight other than `win`, either sign.
+    fn largest_heuristic(&self) -> u64 {
+        let mut weights = *self;
+        weights.fields().into_iter()
+            .filter(|(name, _)| *name != "win")
+            .map(|(_, value)| value.unsigned_abs() as u64)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn load(path: &str) -> Result<Weights, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Can't read {path}: {err}"))?;
+        Weights::parse(&text).map_err(|msg| format!("{path}: {msg}"))
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights::DEFAULT
+    }
+}
+
+// In the file format, so parsing it gives the same weights back.
+impl fmt::Display for Weights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut weights = *self;
+        for (name, value) in weights.fields() {
+            writeln!(f, "{name} = {value}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_weights() {
+        assert_eq!(Weights::parse(include_str!("../weights/default.toml")), Ok(Weights::DEFAULT));
+        assert_eq!(Weights::parse(""), Ok(Weights::DEFAULT));
+
+        let weights = Weights::parse("# Sharper\nthree = 900  # up from 500\n\ncenter=-2\n").unwrap();
+        assert_eq!((weights.three, weights.center, weights.two), (900, -2, 50));
+        assert_eq!(Weights::parse(&weights.to_string()), Ok(weights));
+
+        assert!(Weights::parse("four = 1").is_err());
+        assert!(Weights::parse("three 900").is_err());
+        assert!(Weights::parse("three = lots").is_err());
+        assert!(Weights::parse("win = 0").is_err());
+
+        // Two open threes would be a win.
+        assert!(Weights::parse("win = 1000").is_err());
+        assert!(Weights::parse("win = 200000").is_ok());
+        assert!(Weights::parse("win = 200000\ncenter = -2001").is_err());
+        assert_eq!(Weights { win: 200000, ..Weights::DEFAULT }.heuristic_limit(), 2000);
+        assert!(Weights { zugzwang: 2001, ..Weights { win: 200000, ..Weights::DEFAULT } }.validate().is_err());
+        assert!(Weights::load("no/such/file.toml").is_err());
+    }
+}